@@ -0,0 +1,206 @@
+use crate::math::{segment_sphere_intersection, Torus, FINITE_CUBE_SIZE};
+use bevy::{prelude::*, utils::FixedState};
+use clap::ValueEnum;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+/// How far inside the walls of a box arena they start pushing back
+const WALL_MARGIN: f32 = 100.0;
+/// Acceleration per unit of distance past the start of a wall
+const WALL_STIFFNESS: f32 = 2.0;
+/// Acceleration per unit of distance outside a sphere arena
+const SPHERE_PULL: f32 = 1.0;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ArenaShape {
+    /// A cube whose opposite faces are joined, so flying out of one face
+    /// brings you back in through the other
+    #[default]
+    WrappedCube,
+    /// A box around the origin whose walls push ships back in
+    Box,
+    /// A sphere around the origin that pulls ships back once they stray out
+    Sphere,
+}
+
+/// The playfield. Every peer has to play in the same one.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct Arena {
+    pub shape: ArenaShape,
+    /// Edge length of the cube or box, or diameter of the sphere
+    pub size: f32,
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Arena::new(ArenaShape::WrappedCube, FINITE_CUBE_SIZE)
+    }
+}
+
+impl Arena {
+    pub fn new(shape: ArenaShape, size: f32) -> Self {
+        Arena { shape, size }
+    }
+
+    pub fn wraps(self) -> bool {
+        self.shape == ArenaShape::WrappedCube
+    }
+
+    fn torus(self) -> Torus {
+        Torus::new(self.size)
+    }
+
+    /// Brings a point back into a wrapping arena, leaves it alone otherwise
+    pub fn wrap(self, p: Vec3) -> Vec3 {
+        if self.wraps() {
+            self.torus().wrap(p)
+        } else {
+            p
+        }
+    }
+
+    /// The image of `p` closest to `observer`, `p` itself unless the arena wraps
+    pub fn closest_image(self, observer: Vec3, p: Vec3) -> Vec3 {
+        if self.wraps() {
+            self.torus().closest_image(observer, p)
+        } else {
+            p
+        }
+    }
+
+    /// Shortest vector from `from` to `to`, across the seam if that's shorter
+    pub fn displacement(self, from: Vec3, to: Vec3) -> Vec3 {
+        if self.wraps() {
+            self.torus().displacement(from, to)
+        } else {
+            to - from
+        }
+    }
+
+    pub fn distance(self, a: Vec3, b: Vec3) -> f32 {
+        if self.wraps() {
+            self.torus().distance(a, b)
+        } else {
+            a.distance(b)
+        }
+    }
+
+    pub fn distance_squared(self, a: Vec3, b: Vec3) -> f32 {
+        if self.wraps() {
+            self.torus().distance_squared(a, b)
+        } else {
+            a.distance_squared(b)
+        }
+    }
+
+    /// See [`Torus::lerp_transform`], a plain interpolation unless the arena wraps
+    pub fn lerp_transform(self, a: &Transform, b: &Transform, t: f32) -> Transform {
+        if self.wraps() {
+            self.torus().lerp_transform(a, b, t)
+        } else {
+            Transform {
+                translation: a.translation.lerp(b.translation, t),
+                rotation: a.rotation.slerp(b.rotation, t),
+                scale: a.scale.lerp(b.scale, t),
+            }
+        }
+    }
+
+    /// See [`Torus::images_within`], `p` itself unless the arena wraps
+    pub fn images_within(self, observer: Vec3, p: Vec3, radius: f32) -> Vec<Vec3> {
+        if self.wraps() {
+            self.torus().images_within(observer, p, radius)
+        } else {
+            vec![p]
+        }
+    }
+
+    /// Positions repeat every this many units as far as a
+    /// [`SpatialGrid`](crate::spatial_grid::SpatialGrid) is concerned. Arenas
+    /// that don't wrap get a period bigger than themselves, so only things
+    /// far outside them end up sharing cells.
+    pub fn grid_period(self) -> f32 {
+        if self.wraps() {
+            self.size
+        } else {
+            self.size * 2.0
+        }
+    }
+
+    /// Acceleration pushing something at `p` back towards the middle of a
+    /// bounded arena
+    pub fn boundary_acceleration(self, p: Vec3) -> Vec3 {
+        match self.shape {
+            ArenaShape::WrappedCube => Vec3::ZERO,
+            ArenaShape::Box => {
+                let inner = (self.size * 0.5 - WALL_MARGIN).max(0.0);
+                let outside = p - p.clamp(Vec3::splat(-inner), Vec3::splat(inner));
+                -outside * WALL_STIFFNESS
+            }
+            ArenaShape::Sphere => {
+                let radius = self.size * 0.5;
+                let distance = p.length();
+                if distance <= radius {
+                    Vec3::ZERO
+                } else {
+                    -p / distance * (distance - radius) * SPHERE_PULL
+                }
+            }
+        }
+    }
+
+    /// Sweeps a point against a sphere over one step, both having moved by
+    /// their deltas to end up at `point` and `center`. Returns where the point
+    /// was when it first touched the sphere.
+    pub fn swept_point_sphere_hit(
+        self,
+        center: Vec3,
+        center_delta: Vec3,
+        radius: f32,
+        point: Vec3,
+        point_delta: Vec3,
+    ) -> Option<Vec3> {
+        // sweep in the sphere's frame, so only the relative motion matters
+        let relative_delta = point_delta - center_delta;
+        let start = self.wrap(point) - relative_delta;
+        let t = if self.wraps() {
+            self.torus().segment_sphere_intersection(start, relative_delta, center, radius)
+        } else {
+            segment_sphere_intersection(start, start + relative_delta, center, radius)
+        }?;
+        Some(self.wrap(point - point_delta * (1.0 - t)))
+    }
+}
+
+pub fn checksum_arena(arena: &Arena) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    arena.shape.hash(&mut hasher);
+    arena.size.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweeps_hit_across_the_wrap_seam() {
+        let arena = Arena::default();
+        // flew from x = 5 back over the seam to the far side of the cube,
+        // straight through a ship sitting just inside the near side
+        let center = Vec3::new(2.0, 100.0, 100.0);
+        let point = Vec3::new(FINITE_CUBE_SIZE - 5.0, 100.0, 100.0);
+        let hit = arena
+            .swept_point_sphere_hit(center, Vec3::ZERO, 1.5, point, Vec3::new(-10.0, 0.0, 0.0))
+            .expect("the sweep passes through the ship");
+        assert!(hit.distance(Vec3::new(3.5, 100.0, 100.0)) < 1e-3, "hit at {hit}");
+    }
+
+    #[test]
+    fn sweeps_miss_what_they_pass_by() {
+        let arena = Arena::default();
+        let center = Vec3::new(2.0, 100.0, 100.0);
+        let point = Vec3::new(FINITE_CUBE_SIZE - 5.0, 110.0, 100.0);
+        let hit = arena.swept_point_sphere_hit(center, Vec3::ZERO, 1.5, point, Vec3::new(-10.0, 0.0, 0.0));
+        assert_eq!(hit, None);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::arena::ArenaShape;
+use crate::bots::BotDifficulty;
+use crate::flight::FlightAssist;
+use crate::math::FINITE_CUBE_SIZE;
 use bevy::prelude::*;
 use clap::Parser;
 
@@ -6,6 +10,92 @@ pub struct Args {
     /// runs the game in synctest mode
     #[clap(long)]
     pub synctest: bool,
+    /// runs the simulation without a window or renderer
+    #[clap(long)]
+    pub headless: bool,
+    /// stops a headless match after this many simulated frames, logging the scores
+    #[clap(long)]
+    pub frames: Option<u32>,
+    /// flies a practice match against target drones instead of matchmaking
+    #[clap(long)]
+    pub offline: bool,
+    /// joins the room to watch the match rather than fly in it
+    #[clap(long)]
+    pub spectate: bool,
+    /// number of frames to roll back and resimulate each frame in synctest mode
     #[clap(long, default_value = "2")]
-    pub input_delay: usize,
+    pub check_distance: usize,
+    /// frames of input delay, overrides the config file
+    #[clap(long)]
+    pub input_delay: Option<usize>,
+    /// records every confirmed frame's inputs to this replay file
+    #[clap(long)]
+    pub record: Option<String>,
+    /// plays back a replay file recorded with --record
+    #[clap(long)]
+    pub replay: Option<String>,
+    /// flight model, overrides the config file. Online matches only start
+    /// once every peer flies with the same one
+    #[clap(long, value_enum)]
+    pub flight_assist: Option<FlightAssist>,
+    /// shape of the playfield, every peer has to use the same one
+    #[clap(long, value_enum, default_value_t = ArenaShape::WrappedCube)]
+    pub arena: ArenaShape,
+    /// edge length of the cube or box, or diameter of the sphere
+    #[clap(long, default_value_t = FINITE_CUBE_SIZE, value_parser = parse_arena_size)]
+    pub arena_size: f32,
+    /// asteroid field seed for synctest and headless sessions, online
+    /// matches agree on one of their own
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+    /// signaling server to matchmake through, e.g. one started with the
+    /// signaling_server binary
+    #[clap(long)]
+    pub room_url: Option<String>,
+    /// number of pilots in the match, overrides the config file
+    #[clap(long)]
+    pub players: Option<usize>,
+    /// shows the FPS overlay at startup, overrides the config file
+    #[clap(long)]
+    pub show_fps: Option<bool>,
+    /// pilots sharing this machine offline, 2 for split-screen, overrides
+    /// the config file
+    #[clap(long)]
+    pub local_players: Option<usize>,
+    /// ship to fly, by the name in its ship definition, overrides the config file
+    #[clap(long)]
+    pub ship: Option<String>,
+    /// player slots to fill with bots, overrides the config file
+    #[clap(long)]
+    pub bots: Option<usize>,
+    /// how well bots fly, overrides the config file
+    #[clap(long, value_enum)]
+    pub bot_difficulty: Option<BotDifficulty>,
+    /// RON config file to read, defaults to flying_shooter.ron if it exists
+    #[clap(long)]
+    pub config: Option<String>,
+}
+
+/// Arena sizes end up dividing positions, so they have to be finite and positive
+fn parse_arena_size(s: &str) -> Result<f32, String> {
+    let size: f32 = s.parse().map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    if size.is_finite() && size > 0.0 {
+        Ok(size)
+    } else {
+        Err(format!("arena size has to be a positive number, not {size}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_sizes_have_to_be_positive_and_finite() {
+        for bad in ["0", "-100", "inf", "NaN", "big"] {
+            assert!(Args::try_parse_from(["flying_shooter", "--arena-size", bad]).is_err(), "{bad} was accepted");
+        }
+        let args = Args::try_parse_from(["flying_shooter", "--arena-size", "2000"]).unwrap();
+        assert_eq!(args.arena_size, 2000.0);
+    }
 }
@@ -0,0 +1,268 @@
+use crate::{arena::*, components::*, config::GameConfig, gameplay::*, missiles::MISSILE_SPEED, ships::ShipDefs, math::{split_mix, FINITE_CUBE_SIZE}, spatial_grid::SpatialGrid};
+use bevy::{prelude::*, utils::FixedState};
+use bevy_ggrs::prelude::*;
+use bevy_matchbox::prelude::PeerId;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+/// Asteroids per cubic unit, which makes about 80 in the default arena
+const ASTEROID_DENSITY: f32 = 80.0 / (FINITE_CUBE_SIZE * FINITE_CUBE_SIZE * FINITE_CUBE_SIZE);
+const MIN_SPAWN_RADIUS: f32 = 20.0;
+pub const MAX_ASTEROID_RADIUS: f32 = 60.0;
+/// Fragments smaller than this crumble to dust instead
+const MIN_ASTEROID_RADIUS: f32 = 10.0;
+const FRAGMENT_SCALE: f32 = 0.6;
+const FRAGMENTS_PER_ASTEROID: usize = 2;
+const HEALTH_PER_RADIUS: f32 = 2.0;
+/// Room kept around spawn points so nobody starts the round inside a rock
+const SPAWN_CLEARANCE: f32 = 150.0;
+/// Hull and shield damage per unit of speed a ship crashes into a rock with
+const CRASH_DAMAGE_PER_SPEED: f32 = 1.0;
+/// How much of the speed into a rock a ship keeps bouncing off it
+const CRASH_RESTITUTION: f32 = 0.5;
+
+/// Seed the asteroid field is generated from, agreed on when the session starts
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct AsteroidSeed(pub u64);
+
+/// Hands out the [`AsteroidId`]s that presentation uses to tell asteroids apart
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct NextAsteroidId(u32);
+
+impl NextAsteroidId {
+    fn take(&mut self) -> AsteroidId {
+        let id = AsteroidId(self.0);
+        self.0 = self.0.wrapping_add(1);
+        id
+    }
+}
+
+pub fn checksum_asteroid_seed(seed: &AsteroidSeed) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    seed.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_next_asteroid_id(next_asteroid_id: &NextAsteroidId) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    next_asteroid_id.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A seed every peer of a match arrives at on its own, from the ids of all
+/// the peers in it. Mixed with SplitMix64 rather than a hasher, whose output
+/// could change with its version.
+pub fn field_seed(peers: impl IntoIterator<Item = PeerId>) -> u64 {
+    let mut peers: Vec<u128> = peers.into_iter().map(|peer| peer.0.as_u128()).collect();
+    peers.sort_unstable();
+    peers.iter().fold(0, |seed, peer| {
+        let seed = split_mix(seed ^ *peer as u64);
+        split_mix(seed ^ (*peer >> 64) as u64)
+    })
+}
+
+/// SplitMix64, small and the same on every platform
+struct Rng(u64);
+
+impl Rng {
+    /// A separate stream of numbers for each `stream` under the same seed
+    fn new(seed: u64, stream: u64) -> Self {
+        Rng(seed ^ stream.wrapping_mul(0xD1B5_4A32_D192_ED03))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let z = split_mix(self.0);
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z
+    }
+
+    /// Uniform in `min..max`
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        min + unit * (max - min)
+    }
+
+    fn direction(&mut self) -> Vec3 {
+        loop {
+            let v = Vec3::new(self.range(-1.0, 1.0), self.range(-1.0, 1.0), self.range(-1.0, 1.0));
+            let length_squared = v.length_squared();
+            if length_squared > 0.01 && length_squared <= 1.0 {
+                return v / length_squared.sqrt();
+            }
+        }
+    }
+}
+
+fn spawn_asteroid(commands: &mut Commands, id: AsteroidId, translation: Vec3, radius: f32) {
+    commands
+        .spawn((
+            Asteroid { radius },
+            id,
+            Health(radius * HEALTH_PER_RADIUS),
+            Transform::from_translation(translation),
+        ))
+        .add_rollback();
+}
+
+/// Scatters a fresh field at the start of every round, the same one each round
+pub fn spawn_asteroids(
+    mut commands: Commands,
+    asteroids: Query<Entity, With<Asteroid>>,
+    seed: Res<AsteroidSeed>,
+    arena: Res<Arena>,
+    game_config: Res<GameConfig>,
+    mut next_asteroid_id: ResMut<NextAsteroidId>,
+) {
+    for asteroid in &asteroids {
+        commands.entity(asteroid).despawn_recursive();
+    }
+    *next_asteroid_id = NextAsteroidId::default();
+
+    let spawns: Vec<Vec3> = (0..game_config.num_players)
+        .map(|handle| spawn_transform(handle, game_config.num_players, *arena).translation)
+        .collect();
+    let count = (ASTEROID_DENSITY * arena.size.powi(3)).round() as usize;
+    let mut rng = Rng::new(seed.0, 0);
+    let mut placed = 0;
+    // give up on crowded arenas rather than looking for room forever
+    for _ in 0..count * 10 {
+        if placed == count {
+            break;
+        }
+        let pos = Vec3::new(rng.range(-0.5, 0.5), rng.range(-0.5, 0.5), rng.range(-0.5, 0.5)) * arena.size;
+        let radius = rng.range(MIN_SPAWN_RADIUS, MAX_ASTEROID_RADIUS);
+        if arena.shape == ArenaShape::Sphere && pos.length() + radius > arena.size * 0.5 {
+            continue;
+        }
+        let pos = arena.wrap(pos);
+        let blocks_spawn = spawns
+            .iter()
+            .any(|spawn| arena.distance(*spawn, pos) < radius + SPAWN_CLEARANCE);
+        if blocks_spawn {
+            continue;
+        }
+        spawn_asteroid(&mut commands, next_asteroid_id.take(), pos, radius);
+        placed += 1;
+    }
+}
+
+/// Bullets and missiles chip away at asteroids and are used up doing so.
+/// Shattered asteroids break into smaller ones, scattered the same way on
+/// every peer.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn shoot_asteroids(
+    mut commands: Commands,
+    mut asteroids: Query<(Entity, &AsteroidId, &Asteroid, &Transform, &mut Health), Without<Bullet>>,
+    bullets: Query<(Entity, &BulletId, &Transform, &BulletOwner, &Damage), With<Bullet>>,
+    missiles: Query<(Entity, &BulletId, &Transform, &Missile, &Damage)>,
+    seed: Res<AsteroidSeed>,
+    arena: Res<Arena>,
+    mut next_asteroid_id: ResMut<NextAsteroidId>,
+    time: Res<Time>,
+) {
+    // which rock a bullet hits first has to be the same on every peer
+    let mut asteroids: Vec<_> = asteroids.iter_mut().collect();
+    asteroids.sort_by_key(|(_, asteroid_id, ..)| **asteroid_id);
+    let projectiles = projectiles(bullets.iter(), missiles.iter(), time.delta_seconds());
+
+    let max_step = BULLET_SPEED.max(MISSILE_SPEED) * time.delta_seconds();
+    let mut grid = SpatialGrid::new(arena.grid_period(), COLLISION_CELL_SIZE);
+    for (i, projectile) in projectiles.iter().enumerate() {
+        grid.insert(projectile.translation, i);
+    }
+
+    let mut spent = vec![false; projectiles.len()];
+    for (asteroid_entity, asteroid_id, asteroid, transform, health) in &mut asteroids {
+        let reach = asteroid.radius + BULLET_RADIUS + max_step;
+        for i in grid.query(transform.translation, reach) {
+            let projectile = &projectiles[i];
+            if spent[i] {
+                continue;
+            }
+            let hit = arena.swept_point_sphere_hit(
+                transform.translation,
+                Vec3::ZERO,
+                asteroid.radius + BULLET_RADIUS,
+                projectile.translation,
+                projectile.step,
+            );
+            if hit.is_none() {
+                continue;
+            }
+            spent[i] = true;
+            commands.entity(projectile.entity).despawn_recursive();
+            health.0 -= projectile.damage;
+            if health.0 <= 0.0 {
+                break;
+            }
+        }
+        if health.0 > 0.0 {
+            continue;
+        }
+        commands.entity(*asteroid_entity).despawn_recursive();
+
+        let fragment_radius = asteroid.radius * FRAGMENT_SCALE;
+        if fragment_radius < MIN_ASTEROID_RADIUS {
+            continue;
+        }
+        let mut rng = Rng::new(seed.0, asteroid_id.0 as u64 + 1);
+        for _ in 0..FRAGMENTS_PER_ASTEROID {
+            let pos = arena.wrap(transform.translation + rng.direction() * fragment_radius);
+            spawn_asteroid(&mut commands, next_asteroid_id.take(), pos, fragment_radius);
+        }
+    }
+}
+
+/// Ships that fly into an asteroid bounce off it and take damage for the
+/// speed they hit it with
+#[allow(clippy::type_complexity)]
+pub fn crash_into_asteroids(
+    mut players: Query<(&mut Transform, &mut Velocity, &mut Health, &mut Shield, &ShipClass), (With<Player>, Without<Asteroid>)>,
+    asteroids: Query<(&AsteroidId, &Asteroid, &Transform), Without<Player>>,
+    arena: Res<Arena>,
+    frame: Res<SimulationFrame>,
+    ship_defs: Res<ShipDefs>,
+) {
+    // ships only change themselves, but rocks are bounced off in id order
+    let mut asteroids: Vec<_> = asteroids.iter().collect();
+    asteroids.sort_by_key(|(asteroid_id, ..)| **asteroid_id);
+    let mut grid = SpatialGrid::new(arena.grid_period(), COLLISION_CELL_SIZE);
+    for (i, (_, _, asteroid_transform)) in asteroids.iter().enumerate() {
+        grid.insert(asteroid_transform.translation, i);
+    }
+
+    for (mut transform, mut velocity, mut health, mut shield, class) in &mut players {
+        let radius = ship_defs.get(class.0).radius;
+        for i in grid.query(transform.translation, MAX_ASTEROID_RADIUS + radius) {
+            let (_, asteroid, asteroid_transform) = asteroids[i];
+            let offset = arena.displacement(asteroid_transform.translation, transform.translation);
+            let distance = offset.length();
+            let min_distance = asteroid.radius + radius;
+            if distance >= min_distance || distance == 0.0 {
+                continue;
+            }
+            let normal = offset / distance;
+            transform.translation = arena.wrap(transform.translation + normal * (min_distance - distance));
+            let impact_speed = -velocity.0.dot(normal);
+            if impact_speed > 0.0 {
+                velocity.0 += normal * impact_speed * (1.0 + CRASH_RESTITUTION);
+                apply_damage(&mut health, &mut shield, impact_speed * CRASH_DAMAGE_PER_SPEED, frame.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::Uuid;
+
+    #[test]
+    fn field_seeds_only_depend_on_who_is_in_the_match() {
+        let a = PeerId(Uuid::from_u128(1));
+        let b = PeerId(Uuid::from_u128((2 << 64) | 3));
+        assert_eq!(field_seed([a, b]), field_seed([b, a]));
+        // the same on every platform and with every version of every crate
+        assert_eq!(field_seed([a, b]), 0x3FB0_6439_F304_A374);
+        assert_ne!(field_seed([a]), field_seed([b]));
+    }
+}
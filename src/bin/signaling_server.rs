@@ -0,0 +1,271 @@
+//! A signaling server for offline LAN play and testing, speaking the same
+//! protocol as `matchbox_server`. Peers connecting to `ws://<host>/<room>?next=N`
+//! are matched in groups of N, a room without `next` is one open group.
+//!
+//! Start it, then point the game at it:
+//!
+//! ```text
+//! cargo run --features signaling-server --bin signaling_server -- --host 0.0.0.0:3536
+//! cargo run -- --room-url "ws://127.0.0.1:3536/flying_shooter?next=2"
+//! ```
+
+#[cfg(not(target_family = "wasm"))]
+mod server {
+    use clap::Parser;
+    use futures_util::{SinkExt, StreamExt};
+    use matchbox_protocol::{PeerEvent, PeerId, PeerRequest};
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::{
+        handshake::server::{Request, Response},
+        http::Uri,
+        Message,
+    };
+    use uuid::Uuid;
+
+    #[derive(Parser, Debug)]
+    struct ServerArgs {
+        /// address to listen on
+        #[clap(long, default_value = "0.0.0.0:3536")]
+        host: SocketAddr,
+    }
+
+    /// Peers sharing the same path and `next` value wait in the same group
+    type RoomKey = (String, Option<usize>);
+
+    struct Peer {
+        group: u64,
+        sender: mpsc::UnboundedSender<Message>,
+    }
+
+    #[derive(Default)]
+    struct State {
+        peers: HashMap<PeerId, Peer>,
+        groups: HashMap<u64, Vec<PeerId>>,
+        /// The group new peers join, per room, until it is full
+        open_groups: HashMap<RoomKey, u64>,
+        next_group: u64,
+    }
+
+    impl State {
+        fn send(&self, peer_id: PeerId, event: PeerEvent<Value>) {
+            let Some(peer) = self.peers.get(&peer_id) else { return; };
+            match serde_json::to_string(&event) {
+                Ok(text) => {
+                    let _ = peer.sender.send(Message::Text(text));
+                }
+                Err(e) => eprintln!("failed to serialize {event:?}: {e}"),
+            }
+        }
+
+        fn join(&mut self, peer_id: PeerId, room: RoomKey, sender: mpsc::UnboundedSender<Message>) {
+            let group = match self.open_groups.get(&room) {
+                Some(group) => *group,
+                None => {
+                    let group = self.next_group;
+                    self.next_group += 1;
+                    self.open_groups.insert(room.clone(), group);
+                    group
+                }
+            };
+            self.peers.insert(peer_id, Peer { group, sender });
+            self.send(peer_id, PeerEvent::IdAssigned(peer_id));
+
+            let members = self.groups.entry(group).or_default();
+            let existing = members.clone();
+            members.push(peer_id);
+            let is_full = room.1.is_some_and(|next| members.len() >= next);
+            // existing peers make the WebRTC offers to the newcomer
+            for other in existing {
+                self.send(other, PeerEvent::NewPeer(peer_id));
+            }
+            if is_full {
+                println!("room {room:?} is full, starting a new group");
+                self.open_groups.remove(&room);
+            }
+        }
+
+        fn leave(&mut self, peer_id: PeerId) {
+            let Some(peer) = self.peers.remove(&peer_id) else { return; };
+            let Some(members) = self.groups.get_mut(&peer.group) else { return; };
+            members.retain(|id| *id != peer_id);
+            let remaining = members.clone();
+            if remaining.is_empty() {
+                self.groups.remove(&peer.group);
+                self.open_groups.retain(|_, group| *group != peer.group);
+            }
+            for other in remaining {
+                self.send(other, PeerEvent::PeerLeft(peer_id));
+            }
+        }
+
+        fn forward(&self, sender: PeerId, receiver: PeerId, data: Value) {
+            if !self.peers.contains_key(&receiver) {
+                eprintln!("{sender:?} signaled unknown peer {receiver:?}");
+                return;
+            }
+            self.send(receiver, PeerEvent::Signal { sender, data });
+        }
+    }
+
+    fn room_key(uri: &Uri) -> RoomKey {
+        let next = uri.query().and_then(|query| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == "next")
+                .and_then(|(_, value)| value.parse().ok())
+        });
+        (uri.path().to_string(), next)
+    }
+
+    async fn handle_connection(state: Arc<Mutex<State>>, stream: TcpStream, addr: SocketAddr) {
+        let mut uri = Uri::default();
+        let ws = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| {
+            uri = request.uri().clone();
+            Ok(response)
+        })
+        .await;
+        let ws = match ws {
+            Ok(ws) => ws,
+            Err(e) => {
+                eprintln!("websocket handshake with {addr} failed: {e}");
+                return;
+            }
+        };
+
+        let peer_id = PeerId(Uuid::new_v4());
+        let room = room_key(&uri);
+        println!("{addr} joined {room:?} as {peer_id:?}");
+
+        let (mut ws_sender, mut ws_receiver) = ws.split();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+        state.lock().unwrap().join(peer_id, room, sender);
+
+        let writer = tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                if ws_sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        while let Some(Ok(message)) = ws_receiver.next().await {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            match serde_json::from_str::<PeerRequest<Value>>(&text) {
+                Ok(PeerRequest::Signal { receiver, data }) => {
+                    state.lock().unwrap().forward(peer_id, receiver, data);
+                }
+                Ok(PeerRequest::KeepAlive) => {}
+                Err(e) => eprintln!("bad request from {peer_id:?}: {e}"),
+            }
+        }
+
+        println!("{peer_id:?} left");
+        state.lock().unwrap().leave(peer_id);
+        writer.abort();
+    }
+
+    #[tokio::main]
+    pub async fn main() {
+        let args = ServerArgs::parse();
+        let listener = TcpListener::bind(args.host)
+            .await
+            .unwrap_or_else(|e| panic!("failed to listen on {}: {e}", args.host));
+        println!("signaling server listening on ws://{}", args.host);
+        serve(listener).await;
+    }
+
+    /// Matches up the peers connecting to `listener`, forever
+    async fn serve(listener: TcpListener) {
+        let state = Arc::new(Mutex::new(State::default()));
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    tokio::spawn(handle_connection(state.clone(), stream, addr));
+                }
+                Err(e) => eprintln!("failed to accept connection: {e}"),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::time::Duration;
+        use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+        type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+        const TIMEOUT: Duration = Duration::from_secs(5);
+
+        async fn start_server() -> SocketAddr {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(serve(listener));
+            addr
+        }
+
+        /// Connects and waits for the id the server hands out
+        async fn connect(addr: SocketAddr, room: &str) -> (Client, PeerId) {
+            let (mut client, _) = connect_async(format!("ws://{addr}{room}")).await.unwrap();
+            match next_event(&mut client).await {
+                Some(PeerEvent::IdAssigned(id)) => (client, id),
+                event => panic!("expected an id, got {event:?}"),
+            }
+        }
+
+        /// The next event sent to `client`, or `None` if there's none in time
+        async fn next_event(client: &mut Client) -> Option<PeerEvent<Value>> {
+            loop {
+                let message = tokio::time::timeout(TIMEOUT, client.next()).await.ok()??.unwrap();
+                if let Message::Text(text) = message {
+                    return Some(serde_json::from_str(&text).unwrap());
+                }
+            }
+        }
+
+        #[tokio::test]
+        async fn peers_are_matched_in_groups_and_signals_forwarded() {
+            let addr = start_server().await;
+            let (mut first, first_id) = connect(addr, "/test?next=2").await;
+            let (mut second, second_id) = connect(addr, "/test?next=2").await;
+            assert_ne!(first_id, second_id);
+
+            // only the peer already there hears of the newcomer, and makes the offer
+            assert_eq!(next_event(&mut first).await, Some(PeerEvent::NewPeer(second_id)));
+
+            let data = Value::String("offer".to_string());
+            let signal = PeerRequest::Signal { receiver: second_id, data: data.clone() };
+            first.send(Message::Text(serde_json::to_string(&signal).unwrap())).await.unwrap();
+            assert_eq!(
+                next_event(&mut second).await,
+                Some(PeerEvent::Signal { sender: first_id, data })
+            );
+
+            // the group of two is full, so the next two peers make a new one
+            let (mut third, _) = connect(addr, "/test?next=2").await;
+            let (_fourth, fourth_id) = connect(addr, "/test?next=2").await;
+            assert_eq!(next_event(&mut third).await, Some(PeerEvent::NewPeer(fourth_id)));
+            drop(second);
+            assert_eq!(next_event(&mut first).await, Some(PeerEvent::PeerLeft(second_id)));
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn main() {
+    server::main();
+}
+
+// there's nothing to host from inside a browser
+#[cfg(target_family = "wasm")]
+fn main() {}
@@ -0,0 +1,386 @@
+use crate::{arena::Arena, components::*, gameplay::*, input::Controls, math::split_mix, missiles::*};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ggrs::{LocalInputs, LocalPlayers};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Missiles homing in from closer than this make bots break off and flare
+const MISSILE_EVADE_RANGE: f32 = 400.0;
+/// Ships this close behind a bot, pointing at it, make it jink
+const CHASE_RANGE: f32 = 300.0;
+const CHASE_CONE: f32 = 20.0_f32.to_radians();
+/// Bots open the throttle to close in from further than this...
+const CLOSE_IN_RANGE: f32 = 400.0;
+/// ...and ease off when closer than this, so they don't overshoot
+const OVERSHOOT_RANGE: f32 = 80.0;
+const FIRE_RANGE: f32 = 800.0;
+/// Off-axis angle at which bots stop rolling the target into their pitch
+/// plane and just pull, so they don't twitch on targets dead ahead
+const FINE_AIM_ANGLE: f32 = 10.0_f32.to_radians();
+/// Stick per radian the nose or wings are off
+const PITCH_GAIN: f32 = 2.0;
+const ROLL_GAIN: f32 = 2.0;
+/// Frames a jink keeps turning the same way
+const JINK_FRAMES: u32 = 45;
+/// Frames the aim wobble keeps pointing the same way
+const WOBBLE_FRAMES: u32 = 30;
+/// Bots press the missile button for a second out of every this many
+const MISSILE_PRESS_SECONDS: u32 = 6;
+/// Frames a drone holds the same lazy turn
+const WANDER_FRAMES: u32 = 120;
+/// Fraction of full stick drones turn with at most
+const WANDER_STICK: f32 = 0.3;
+
+/// How hard bots are to beat. Only the peer flying a bot needs to know.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BotDifficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    /// Practice target that cruises around in lazy turns and never fights back
+    #[value(skip)]
+    #[serde(skip)]
+    Drone,
+}
+
+struct Skill {
+    /// Frames between decisions, the last input is held in between
+    reaction_frames: u32,
+    /// Fraction of full stick it's willing to use
+    stick: f32,
+    /// How far from the point it means to aim at its aim wanders
+    aim_error: f32,
+    /// How far off its aim point it still pulls the trigger
+    fire_cone: f32,
+    /// Whether it aims where the target will be rather than where it is
+    leads: bool,
+    evades: bool,
+    fires_missiles: bool,
+    /// Whether it goes after other ships at all, rather than just wandering
+    fights: bool,
+}
+
+impl BotDifficulty {
+    fn skill(self) -> Skill {
+        match self {
+            BotDifficulty::Easy => Skill {
+                reaction_frames: 20,
+                stick: 0.5,
+                aim_error: 25.0,
+                fire_cone: 8.0_f32.to_radians(),
+                leads: false,
+                evades: false,
+                fires_missiles: false,
+                fights: true,
+            },
+            BotDifficulty::Normal => Skill {
+                reaction_frames: 8,
+                stick: 0.8,
+                aim_error: 10.0,
+                fire_cone: 5.0_f32.to_radians(),
+                leads: true,
+                evades: true,
+                fires_missiles: false,
+                fights: true,
+            },
+            BotDifficulty::Hard => Skill {
+                reaction_frames: 3,
+                stick: 1.0,
+                aim_error: 3.0,
+                fire_cone: 4.0_f32.to_radians(),
+                leads: true,
+                evades: true,
+                fires_missiles: true,
+                fights: true,
+            },
+            BotDifficulty::Drone => Skill {
+                reaction_frames: 30,
+                stick: WANDER_STICK,
+                aim_error: 0.0,
+                fire_cone: 0.0,
+                leads: false,
+                evades: false,
+                fires_missiles: false,
+                fights: false,
+            },
+        }
+    }
+}
+
+/// The local player handles this peer flies with bots, and how well.
+///
+/// Bots only ever make inputs, which GGRS hands to every peer and replays on
+/// rollback like any other pilot's, so peers never have to agree on what a
+/// bot was thinking for the simulation to stay deterministic.
+#[derive(Resource, Debug, Default)]
+pub struct Bots {
+    pilots: Vec<(usize, BotDifficulty)>,
+    /// Input each bot holds until its next decision
+    held: HashMap<usize, [u8; 3]>,
+}
+
+impl Bots {
+    pub fn new(handles: impl IntoIterator<Item = usize>, difficulty: BotDifficulty) -> Self {
+        Bots::default().with(handles, difficulty)
+    }
+
+    /// Adds bots flying `handles` at `difficulty`
+    pub fn with(mut self, handles: impl IntoIterator<Item = usize>, difficulty: BotDifficulty) -> Self {
+        self.pilots.extend(handles.into_iter().map(|handle| (handle, difficulty)));
+        self
+    }
+
+    pub fn flies(&self, handle: usize) -> bool {
+        self.pilots.iter().any(|(bot, _)| *bot == handle)
+    }
+}
+
+/// Player handles flown from this machine by a person rather than a bot:
+/// handle 0 before a session has started, and the first local bot if every
+/// local player is one, so there's still someone to watch
+pub fn pilot_handles(local_players: &Option<Res<LocalPlayers>>, bots: &Option<Res<Bots>>) -> Vec<usize> {
+    let Some(local_players) = local_players.as_ref().filter(|local_players| !local_players.0.is_empty()) else {
+        return vec![0];
+    };
+    let is_bot = |handle: &usize| bots.as_ref().is_some_and(|bots| bots.flies(*handle));
+    let mut handles: Vec<usize> = local_players.0.iter().copied().filter(|handle| !is_bot(handle)).collect();
+    if handles.is_empty() {
+        handles.extend(local_players.0.iter().copied().min());
+    }
+    handles
+}
+
+/// Deterministic noise in -1..1 that changes whenever `tick` does
+fn noise(handle: usize, tick: u32, salt: u32) -> f32 {
+    let z = split_mix(split_mix(split_mix(handle as u64) ^ tick as u64) ^ salt as u64);
+    (z >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+/// Where to aim for bullets fired from `pos` to meet a target at
+/// `target_pos` flying at `target_velocity`. Bullets fly at a fixed speed,
+/// so where they'd meet is refined twice.
+fn lead(pos: Vec3, target_pos: Vec3, target_velocity: Vec3) -> Vec3 {
+    let mut aim = target_pos;
+    for _ in 0..2 {
+        aim = target_pos + target_velocity * (aim.distance(pos) / BULLET_SPEED);
+    }
+    aim
+}
+
+/// Pitch and roll stick that roll `local`, a point in ship space, into the
+/// plane the ship pitches in and pull the nose onto it
+fn steer(local: Vec3) -> (f32, f32) {
+    // positive pitch lowers the nose
+    let pitch_error = -local.y.atan2(local.z);
+    // roll whichever way puts the point straight above or below
+    let roll_error = if local.y != 0.0 {
+        (-local.x / local.y).atan()
+    } else if local.x != 0.0 {
+        -local.x.signum() * std::f32::consts::FRAC_PI_2
+    } else {
+        0.0
+    };
+    let off_axis = local.truncate().length().atan2(local.z);
+    let roll_weight = (off_axis / FINE_AIM_ANGLE).min(1.0);
+    (
+        (pitch_error * PITCH_GAIN).clamp(-1.0, 1.0),
+        (roll_error * ROLL_GAIN * roll_weight).clamp(-1.0, 1.0),
+    )
+}
+
+/// Everything a bot looks at to decide what to do
+struct Situation<'a> {
+    arena: Arena,
+    frame: u32,
+    ships: &'a [(&'a Transform, &'a Velocity, &'a Throttle, &'a Player)],
+    missiles: &'a [(&'a Transform, &'a Missile)],
+}
+
+impl Situation<'_> {
+    fn controls(&self, handle: usize, skill: &Skill) -> Controls {
+        if !skill.fights {
+            return self.wander(handle, skill);
+        }
+        let Some(&(me, _, throttle, _)) = self.ships.iter().find(|(.., player)| player.handle == handle) else {
+            return Controls::default();
+        };
+        let pos = me.translation;
+        let nose = me.rotation * Vec3::Z;
+        let mut controls = Controls::default();
+
+        // a missile closing in comes first: cut the throttle, which drops
+        // flares once it's idle, and break hard
+        if skill.evades {
+            let incoming = self
+                .missiles
+                .iter()
+                .filter(|(_, missile)| missile.target == MissileTarget::Ship(handle))
+                .any(|(transform, _)| self.arena.distance(pos, transform.translation) < MISSILE_EVADE_RANGE);
+            if incoming {
+                (controls.pitch, controls.roll) = self.jink(handle, skill);
+                controls.throttle = -1.0;
+                return controls;
+            }
+        }
+
+        // go after the closest other ship, the lowest handle on a tie
+        let target = self
+            .ships
+            .iter()
+            .filter(|(.., player)| player.handle != handle)
+            .min_by(|a, b| {
+                let a = self.arena.distance_squared(pos, a.0.translation);
+                a.total_cmp(&self.arena.distance_squared(pos, b.0.translation))
+            });
+        let Some(&(target, target_velocity, ..)) = target else {
+            return controls;
+        };
+        let target_pos = pos + self.arena.displacement(pos, target.translation);
+        let (target_rotation, target_velocity) = (target.rotation, target_velocity.0);
+        let distance = target_pos.distance(pos);
+
+        // someone on our tail with us in their sights: jink and run
+        if skill.evades && distance < CHASE_RANGE && nose.dot(target_pos - pos) < 0.0 {
+            let their_nose = target_rotation * Vec3::Z;
+            if their_nose.angle_between(pos - target_pos) < CHASE_CONE {
+                (controls.pitch, controls.roll) = self.jink(handle, skill);
+                controls.throttle = 1.0;
+                return controls;
+            }
+        }
+
+        let mut aim = if skill.leads { lead(pos, target_pos, target_velocity) } else { target_pos };
+        let tick = self.frame / WOBBLE_FRAMES;
+        aim += Vec3::new(noise(handle, tick, 0), noise(handle, tick, 1), noise(handle, tick, 2)) * skill.aim_error;
+
+        let (pitch, roll) = steer(me.rotation.inverse() * (aim - pos));
+        controls.pitch = pitch * skill.stick;
+        controls.roll = roll * skill.stick;
+        if distance > CLOSE_IN_RANGE {
+            controls.throttle = 1.0;
+        } else if distance < OVERSHOOT_RANGE && throttle.0 > 0.5 {
+            controls.throttle = -1.0;
+        }
+
+        let off_nose = nose.angle_between(aim - pos);
+        controls.fire = distance < FIRE_RANGE && off_nose < skill.fire_cone;
+        // missiles launch on the press, so hold it for a second now and then
+        let second = self.frame / 60;
+        controls.missile = skill.fires_missiles
+            && distance < LOCK_RANGE
+            && off_nose < LOCK_CONE
+            && (second + handle as u32) % MISSILE_PRESS_SECONDS == 0;
+        controls
+    }
+
+    /// Gentle turns that change direction every so often, at cruising speed
+    fn wander(&self, handle: usize, skill: &Skill) -> Controls {
+        let tick = self.frame / WANDER_FRAMES;
+        Controls {
+            pitch: noise(handle, tick, 5) * skill.stick,
+            roll: noise(handle, tick, 6) * skill.stick,
+            ..default()
+        }
+    }
+
+    /// Hard turns that change direction every so often
+    fn jink(&self, handle: usize, skill: &Skill) -> (f32, f32) {
+        let tick = self.frame / JINK_FRAMES;
+        let pitch = if noise(handle, tick, 3) < 0.0 { -1.0 } else { 1.0 };
+        let roll = if noise(handle, tick, 4) < 0.0 { -1.0 } else { 1.0 };
+        (pitch * skill.stick, roll * skill.stick)
+    }
+}
+
+/// Replaces the local inputs of bot handles with what the bots decide to do
+pub fn drive_bots(
+    mut bots: ResMut<Bots>,
+    mut local_inputs: ResMut<LocalInputs<Config>>,
+    players: Query<(&Transform, &Velocity, &Throttle, &Player)>,
+    missiles: Query<(&Transform, &Missile)>,
+    frame: Res<SimulationFrame>,
+    arena: Res<Arena>,
+) {
+    let mut ships: Vec<_> = players.iter().collect();
+    ships.sort_by_key(|(.., player)| player.handle);
+    let missiles: Vec<_> = missiles.iter().collect();
+    let situation = Situation {
+        arena: *arena,
+        frame: frame.0,
+        ships: &ships,
+        missiles: &missiles,
+    };
+
+    let bots = &mut *bots;
+    for &(handle, difficulty) in &bots.pilots {
+        let skill = difficulty.skill();
+        let decide = (frame.0 + handle as u32) % skill.reaction_frames == 0;
+        let input = match bots.held.get(&handle) {
+            Some(input) if !decide => *input,
+            _ => situation.controls(handle, &skill).to_input(),
+        };
+        bots.held.insert(handle, input);
+        local_inputs.0.insert(handle, input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::ArenaShape;
+    use crate::math::FINITE_CUBE_SIZE;
+
+    #[test]
+    fn targets_straight_ahead_need_no_stick() {
+        assert_eq!(steer(Vec3::new(0.0, 0.0, 100.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn targets_above_pull_the_nose_up() {
+        let (pitch, roll) = steer(Vec3::new(0.0, 50.0, 100.0));
+        // positive pitch lowers the nose
+        assert!(pitch < 0.0, "pitch {pitch}");
+        assert_eq!(roll, 0.0);
+        let (pitch, _) = steer(Vec3::new(0.0, -50.0, 100.0));
+        assert!(pitch > 0.0, "pitch {pitch}");
+    }
+
+    #[test]
+    fn bullets_are_aimed_where_they_meet_the_target() {
+        let target = Vec3::new(0.0, 0.0, 400.0);
+        assert_eq!(lead(Vec3::ZERO, target, Vec3::ZERO), target);
+
+        let velocity = Vec3::new(50.0, 0.0, 0.0);
+        let aim = lead(Vec3::ZERO, target, velocity);
+        assert!(aim.x > 0.0 && aim.y == 0.0 && aim.z == target.z, "aim {aim}");
+        // the target gets there about when the bullet does
+        let bullet_time = aim.length() / BULLET_SPEED;
+        let target_time = aim.distance(target) / velocity.length();
+        assert!((bullet_time - target_time).abs() < 0.01 * bullet_time, "{bullet_time} vs {target_time}");
+    }
+
+    #[test]
+    fn easy_bots_only_fire_at_targets_in_their_cone() {
+        let me = Transform::IDENTITY;
+        let still = Velocity(Vec3::ZERO);
+        let throttle = Throttle(0.0);
+        let (bot, pilot) = (Player { handle: 0 }, Player { handle: 1 });
+        let fires = |angle: f32, frame: u32| {
+            let target = Transform::from_translation(Quat::from_rotation_y(angle) * Vec3::Z * 500.0);
+            let ships = [(&me, &still, &throttle, &bot), (&target, &still, &throttle, &pilot)];
+            let situation = Situation {
+                arena: Arena::new(ArenaShape::WrappedCube, FINITE_CUBE_SIZE),
+                frame,
+                ships: &ships,
+                missiles: &[],
+            };
+            situation.controls(0, &BotDifficulty::Easy.skill()).fire
+        };
+        // whichever way the aim wobbles
+        for frame in (0..1200).step_by(WOBBLE_FRAMES as usize) {
+            assert!(fires(0.0, frame), "held fire on a target dead ahead at frame {frame}");
+            assert!(!fires(30.0_f32.to_radians(), frame), "fired outside the cone at frame {frame}");
+        }
+    }
+}
@@ -1,9 +1,15 @@
 use bevy::{prelude::*, utils::FixedState};
 use std::hash::{BuildHasher, Hash, Hasher};
+use std::marker::PhantomData;
 
 #[derive(Component)]
 pub struct Skybox;
 
+/// 3D camera chasing the pilot at this index among the pilots at this
+/// machine, each in its own part of the window
+#[derive(Component, Clone, Copy)]
+pub struct PilotCamera(pub usize);
+
 #[derive(Component)]
 pub struct AwaitingPlayersRoot;
 
@@ -12,24 +18,62 @@ pub struct Player {
     pub handle: usize,
 }
 
+/// Index of the [`ShipDef`](crate::ships::ShipDef) a ship is built from
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShipClass(pub usize);
+
+/// Draws a player's ship at one image of the wrapped cube, `ghost` 0 being
+/// the closest one and the rest further images within view
 #[derive(Component, Clone, Copy)]
 pub struct FollowPlayer {
     pub target_player_handle: usize,
+    /// The model it was spawned with is only used for ships of this class
+    pub ship_class: usize,
+    pub ghost: usize,
 }
 
-#[derive(Component, Clone, Copy)]
-pub struct FollowBullet {
-    pub index: usize,
+/// Draws the bullet, missile, flare or asteroid, whichever `Marker` is, with
+/// this `Id` at one image of the wrapped cube, like [`FollowPlayer`]
+#[derive(Component)]
+pub struct FollowMesh<Marker, Id> {
+    pub id: Id,
+    pub ghost: usize,
+    marker: PhantomData<fn() -> Marker>,
 }
 
-#[derive(Component, Clone, Copy)]
-pub struct BulletReady(pub bool);
+impl<Marker, Id> FollowMesh<Marker, Id> {
+    pub fn new(id: Id, ghost: usize) -> Self {
+        FollowMesh { id, ghost, marker: PhantomData }
+    }
+}
 
-#[derive(Component)]
+/// State of a ship's cannons that carries over from shot to shot
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Weapon {
+    /// Frames left until the next volley can be fired
+    pub cooldown: u32,
+    /// Builds up with every volley and bleeds off over time
+    pub heat: f32,
+    /// Set when heat maxes out, and cleared once it has cooled down enough
+    pub overheated: bool,
+}
+
+#[derive(Component, Clone, Copy)]
 pub struct Bullet;
 
+/// Identifies a bullet, missile or flare the same way on every peer and
+/// across resimulation
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BulletId(pub u32);
+
+#[derive(Component, Clone, Copy)]
+pub struct BulletOwner {
+    pub handle: usize,
+}
+
+/// The `SimulationFrame` an entity was spawned on
 #[derive(Component, Clone, Copy)]
-pub struct BirthTime(pub f32);
+pub struct BirthFrame(pub u32);
 
 #[derive(Component, Clone, Copy)]
 pub struct Velocity(pub Vec3);
@@ -40,6 +84,79 @@ pub struct Speed(pub f32);
 #[derive(Component, Clone, Copy)]
 pub struct Acceleration(pub Vec3);
 
+/// Engine power, from 0 (idle) to 1 (full throttle)
+#[derive(Component, Clone, Copy)]
+pub struct Throttle(pub f32);
+
+/// Seconds of boost left
+#[derive(Component, Clone, Copy)]
+pub struct BoostFuel(pub f32);
+
+/// Hull points left, the ship is destroyed when they reach zero
+#[derive(Component, Clone, Copy)]
+pub struct Health(pub f32);
+
+/// Absorbs damage before the hull takes any, and recharges once the ship
+/// hasn't been hit for a while
+#[derive(Component, Clone, Copy)]
+pub struct Shield {
+    pub charge: f32,
+    /// The `SimulationFrame` the ship was last hit on
+    pub last_hit_frame: u32,
+}
+
+/// Damage a projectile deals on impact
+#[derive(Component, Clone, Copy)]
+pub struct Damage(pub f32);
+
+/// What a missile's seeker is locked onto
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MissileTarget {
+    /// Flies straight on
+    None,
+    /// The player with this handle
+    Ship(usize),
+    /// A decoy it was lured onto
+    Flare(BulletId),
+}
+
+/// Homes in on its target, turning at a limited rate
+#[derive(Component, Clone, Copy)]
+pub struct Missile {
+    pub owner: usize,
+    pub target: MissileTarget,
+}
+
+/// Missiles left to fire
+#[derive(Component, Clone, Copy)]
+pub struct MissileAmmo(pub u32);
+
+/// Whether the missile button has been let go since the last launch
+#[derive(Component, Clone, Copy)]
+pub struct MissileReady(pub bool);
+
+/// A hot decoy that lures missiles away from the ship that dropped it
+#[derive(Component, Clone, Copy)]
+pub struct Flare;
+
+/// Flares left to drop
+#[derive(Component, Clone, Copy)]
+pub struct FlareAmmo(pub u32);
+
+/// The `SimulationFrame` the next flare can be dropped on
+#[derive(Component, Clone, Copy)]
+pub struct FlareCooldown(pub u32);
+
+/// A rock that doesn't move, and breaks into smaller ones when shot to pieces
+#[derive(Component, Clone, Copy)]
+pub struct Asteroid {
+    pub radius: f32,
+}
+
+/// Identifies an asteroid the same way on every peer and across resimulation
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AsteroidId(pub u32);
+
 pub fn checksum_transform(transform: &Transform) -> u64 {
     let mut hasher = FixedState.build_hasher();
 
@@ -61,3 +178,141 @@ pub fn checksum_transform(transform: &Transform) -> u64 {
 
     hasher.finish()
 }
+
+fn hash_vec3(v: Vec3, hasher: &mut impl Hasher) {
+    assert!(v.is_finite(), "Hashing is not stable for NaN f32 values.");
+
+    v.x.to_bits().hash(hasher);
+    v.y.to_bits().hash(hasher);
+    v.z.to_bits().hash(hasher);
+}
+
+pub fn checksum_player(player: &Player) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    player.handle.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_ship_class(ship_class: &ShipClass) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    ship_class.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_weapon(weapon: &Weapon) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    weapon.cooldown.hash(&mut hasher);
+    weapon.heat.to_bits().hash(&mut hasher);
+    weapon.overheated.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_bullet_id(bullet_id: &BulletId) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    bullet_id.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_speed(speed: &Speed) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    speed.0.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_bullet_owner(owner: &BulletOwner) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    owner.handle.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_birth_frame(birth_frame: &BirthFrame) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    birth_frame.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_velocity(velocity: &Velocity) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    hash_vec3(velocity.0, &mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_throttle(throttle: &Throttle) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    throttle.0.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_boost_fuel(boost_fuel: &BoostFuel) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    boost_fuel.0.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_acceleration(acceleration: &Acceleration) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    hash_vec3(acceleration.0, &mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_health(health: &Health) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    health.0.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_shield(shield: &Shield) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    shield.charge.to_bits().hash(&mut hasher);
+    shield.last_hit_frame.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_damage(damage: &Damage) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    damage.0.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_asteroid(asteroid: &Asteroid) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    asteroid.radius.to_bits().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_asteroid_id(asteroid_id: &AsteroidId) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    asteroid_id.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_missile(missile: &Missile) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    missile.owner.hash(&mut hasher);
+    missile.target.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_missile_ammo(missile_ammo: &MissileAmmo) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    missile_ammo.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_missile_ready(missile_ready: &MissileReady) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    missile_ready.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_flare_ammo(flare_ammo: &FlareAmmo) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    flare_ammo.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_flare_cooldown(flare_cooldown: &FlareCooldown) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    flare_cooldown.0.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,262 @@
+use crate::{args::Args, bots::BotDifficulty, flight::FlightAssist, input::{GamepadConfig, KeyBindings}};
+use bevy::{log::Level, prelude::*};
+use serde::Deserialize;
+use std::path::Path;
+
+pub const MAX_PLAYERS: usize = 8;
+/// Pilots that can share one machine, each with half of the screen
+pub const MAX_LOCAL_PLAYERS: usize = 2;
+
+/// Read when no `--config` is given, if it exists
+const DEFAULT_CONFIG_PATH: &str = "flying_shooter.ron";
+
+/// Settings resolved from, in increasing priority: defaults, the config
+/// file, command-line flags and the page's query string
+#[derive(Resource, Debug, Clone)]
+pub struct GameConfig {
+    pub room_url: String,
+    pub num_players: usize,
+    pub input_delay: usize,
+    pub show_fps: bool,
+    /// Pilots flying from this machine offline, split-screen if more than one
+    pub local_players: usize,
+    /// Keys of the first pilot, or the only one
+    pub key_bindings: KeyBindings,
+    /// Keys of the second pilot in split-screen
+    pub key_bindings_2: KeyBindings,
+    /// Deadzones, inversion and which pilot flies with which gamepad
+    pub gamepad: GamepadConfig,
+    /// Name of the ship to fly, the first one there is if not given
+    pub ship: Option<String>,
+    /// Player slots flown by bots rather than people. Online matches always
+    /// leave at least one of the `num_players` slots for a person.
+    pub bots: usize,
+    pub bot_difficulty: BotDifficulty,
+    /// Join the room to watch rather than fly
+    pub spectate: bool,
+    /// Has to be the same for every peer, online matches check it before starting
+    pub flight_assist: FlightAssist,
+}
+
+/// Messages from before the app, and with it the log, is set up. They'd be
+/// lost if logged right away, so [`flush_startup_log`] logs them once it runs.
+#[derive(Resource, Default, Debug)]
+pub struct StartupLog(Vec<(Level, String)>);
+
+impl StartupLog {
+    pub fn info(&mut self, message: String) {
+        self.0.push((Level::INFO, message));
+    }
+
+    pub fn warn(&mut self, message: String) {
+        self.0.push((Level::WARN, message));
+    }
+
+    pub fn error(&mut self, message: String) {
+        self.0.push((Level::ERROR, message));
+    }
+}
+
+pub fn flush_startup_log(mut log: ResMut<StartupLog>) {
+    for (level, message) in log.0.drain(..) {
+        if level == Level::ERROR {
+            error!("{message}");
+        } else if level == Level::WARN {
+            warn!("{message}");
+        } else {
+            info!("{message}");
+        }
+    }
+}
+
+fn default_room_url(num_players: usize) -> String {
+    format!("wss://dune-breezy-honeysuckle.glitch.me/?next={num_players}")
+}
+
+/// One layer of settings, anything left out falls through to the layer below
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ConfigLayer {
+    room_url: Option<String>,
+    players: Option<usize>,
+    input_delay: Option<usize>,
+    show_fps: Option<bool>,
+    local_players: Option<usize>,
+    key_bindings: Option<KeyBindings>,
+    key_bindings_2: Option<KeyBindings>,
+    gamepad: Option<GamepadConfig>,
+    ship: Option<String>,
+    bots: Option<usize>,
+    bot_difficulty: Option<BotDifficulty>,
+    spectate: Option<bool>,
+    flight_assist: Option<FlightAssist>,
+}
+
+impl ConfigLayer {
+    fn merge(&mut self, over: ConfigLayer) {
+        self.room_url = over.room_url.or(self.room_url.take());
+        self.players = over.players.or(self.players);
+        self.input_delay = over.input_delay.or(self.input_delay);
+        self.show_fps = over.show_fps.or(self.show_fps);
+        self.local_players = over.local_players.or(self.local_players);
+        self.key_bindings = over.key_bindings.or(self.key_bindings.take());
+        self.key_bindings_2 = over.key_bindings_2.or(self.key_bindings_2.take());
+        self.gamepad = over.gamepad.or(self.gamepad.take());
+        self.ship = over.ship.or(self.ship.take());
+        self.bots = over.bots.or(self.bots);
+        self.bot_difficulty = over.bot_difficulty.or(self.bot_difficulty);
+        self.spectate = over.spectate.or(self.spectate);
+        self.flight_assist = over.flight_assist.or(self.flight_assist);
+    }
+
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    fn from_args(args: &Args) -> Self {
+        ConfigLayer {
+            room_url: args.room_url.clone(),
+            players: args.players,
+            input_delay: args.input_delay,
+            show_fps: args.show_fps,
+            local_players: args.local_players,
+            key_bindings: None,
+            key_bindings_2: None,
+            gamepad: None,
+            ship: args.ship.clone(),
+            bots: args.bots,
+            bot_difficulty: args.bot_difficulty,
+            spectate: args.spectate.then_some(true),
+            flight_assist: args.flight_assist,
+        }
+    }
+
+    /// `key,value` pairs from the query string, see `url_params()`
+    #[cfg(any(target_family = "wasm", test))]
+    fn from_url_params(params: Vec<String>) -> Self {
+        let mut layer = ConfigLayer::default();
+        for x in params {
+            let y: Vec<&str> = x.split(",").collect();
+            if y.len() != 2 {
+                continue;
+            }
+            match y[0] {
+                "room_url" => layer.room_url = Some(y[1].into()),
+                "players" => layer.players = y[1].parse().ok(),
+                "input_delay" => layer.input_delay = y[1].parse().ok(),
+                "show_fps" => layer.show_fps = y[1].parse().ok(),
+                "local_players" => layer.local_players = y[1].parse().ok(),
+                "ship" => layer.ship = Some(y[1].into()),
+                "bots" => layer.bots = y[1].parse().ok(),
+                "spectate" => layer.spectate = y[1].parse().ok(),
+                "bot_difficulty" => layer.bot_difficulty = <BotDifficulty as clap::ValueEnum>::from_str(y[1], true).ok(),
+                "flight_assist" => layer.flight_assist = <FlightAssist as clap::ValueEnum>::from_str(y[1], true).ok(),
+                _ => {}
+            }
+        }
+        layer
+    }
+}
+
+impl GameConfig {
+    pub fn load(args: &Args, log: &mut StartupLog) -> Self {
+        let mut layer = ConfigLayer::default();
+
+        match &args.config {
+            Some(path) => match ConfigLayer::from_file(Path::new(path)) {
+                Ok(file) => layer.merge(file),
+                Err(e) => log.error(format!("failed to read config file {path}: {e}")),
+            },
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                match ConfigLayer::from_file(Path::new(DEFAULT_CONFIG_PATH)) {
+                    Ok(file) => layer.merge(file),
+                    Err(e) => log.error(format!("failed to read config file {DEFAULT_CONFIG_PATH}: {e}")),
+                }
+            }
+            None => {}
+        }
+
+        layer.merge(ConfigLayer::from_args(args));
+
+        #[cfg(target_family = "wasm")]
+        layer.merge(ConfigLayer::from_url_params(crate::game::url_params()));
+
+        Self::resolve(layer)
+    }
+
+    /// Fills in the defaults for whatever no layer set
+    fn resolve(layer: ConfigLayer) -> Self {
+        let num_players = layer.players.unwrap_or(2).clamp(1, MAX_PLAYERS);
+        let bots = layer.bots.unwrap_or(0).min(num_players);
+        let local_players = layer.local_players.unwrap_or(1).clamp(1, MAX_LOCAL_PLAYERS);
+        // sharing the keyboard, the first pilot only gets the keys on the left
+        let default_key_bindings = if local_players > 1 { KeyBindings::left_hand() } else { KeyBindings::default() };
+        GameConfig {
+            // the room only matches up the people, bots join in afterwards
+            room_url: layer.room_url.unwrap_or_else(|| default_room_url(num_players - bots.min(num_players - 1))),
+            num_players,
+            input_delay: layer.input_delay.unwrap_or(2),
+            show_fps: layer.show_fps.unwrap_or(true),
+            local_players,
+            key_bindings: layer.key_bindings.unwrap_or(default_key_bindings),
+            key_bindings_2: layer.key_bindings_2.unwrap_or_else(KeyBindings::right_hand),
+            gamepad: layer.gamepad.unwrap_or_default(),
+            ship: layer.ship,
+            bots,
+            bot_difficulty: layer.bot_difficulty.unwrap_or_default(),
+            spectate: layer.spectate.unwrap_or(false),
+            flight_assist: layer.flight_assist.unwrap_or_default(),
+        }
+    }
+
+    /// Keys the `pilot`th of the pilots at this machine flies with. A lone
+    /// pilot's keys fly every local handle, like in synctest sessions.
+    pub fn key_bindings_for(&self, pilot: usize) -> Option<&KeyBindings> {
+        match (self.local_players, pilot) {
+            (1, _) | (_, 0) => Some(&self.key_bindings),
+            (_, 1) => Some(&self.key_bindings_2),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn later_layers_win() {
+        let file: ConfigLayer = ron::from_str("(players: Some(3), input_delay: Some(4), ship: Some(\"X-Wing\"), bots: Some(1))").unwrap();
+        let args = Args::try_parse_from(["flying_shooter", "--players", "5", "--ship", "Interceptor", "--bots", "2"]).unwrap();
+        let url = ConfigLayer::from_url_params(vec!["bots,3".to_string()]);
+
+        let mut layer = ConfigLayer::default();
+        layer.merge(file);
+        layer.merge(ConfigLayer::from_args(&args));
+        layer.merge(url);
+        let config = GameConfig::resolve(layer);
+
+        // only the defaults
+        assert_eq!(config.local_players, 1);
+        assert!(config.show_fps);
+        // the file over the defaults
+        assert_eq!(config.input_delay, 4);
+        // the command line over the file
+        assert_eq!(config.num_players, 5);
+        assert_eq!(config.ship.as_deref(), Some("Interceptor"));
+        // the query string over the command line
+        assert_eq!(config.bots, 3);
+    }
+
+    #[test]
+    fn nothing_set_leaves_the_defaults() {
+        let config = GameConfig::resolve(ConfigLayer::default());
+        assert_eq!(config.num_players, 2);
+        assert_eq!(config.input_delay, 2);
+        assert_eq!(config.bots, 0);
+        assert_eq!(config.ship, None);
+        assert_eq!(config.room_url, default_room_url(2));
+    }
+}
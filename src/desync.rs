@@ -0,0 +1,345 @@
+use crate::{arena::*, asteroids::*, components::*, gameplay::*, ships::*};
+use bevy::prelude::*;
+use bevy_ggrs::GgrsSchedule;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+
+/// How many simulated frames to keep dumps of. Desyncs are reported a few
+/// frames after they happen, once the remote checksum has arrived.
+const MAX_FRAME_DUMPS: usize = 128;
+
+/// Keeps a per entity and per component dump of the recent rollback state,
+/// so the state at a desynced frame can be written out and diffed between peers
+pub struct DesyncPlugin;
+
+impl Plugin for DesyncPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FrameDumps>()
+            .add_systems(
+                GgrsSchedule,
+                (apply_deferred, capture_frame_dump)
+                    .chain()
+                    .after(tick_simulation_frame)
+                    .after(kill_players)
+                    .after(round_end_timeout),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct FrameDumps(VecDeque<FrameState>);
+
+impl FrameDumps {
+    fn insert(&mut self, state: FrameState) {
+        // a resimulated frame replaces the state of its earlier prediction
+        self.0.retain(|kept| kept.frame < state.frame);
+        self.0.push_back(state);
+        while self.0.len() > MAX_FRAME_DUMPS {
+            self.0.pop_front();
+        }
+    }
+
+    fn get(&self, frame: u32) -> Option<&FrameState> {
+        self.0.iter().find(|state| state.frame == frame)
+    }
+}
+
+/// Sums the checksums of every instance of each component
+#[derive(Default)]
+struct ChecksumTotals(Vec<(&'static str, u64)>);
+
+impl ChecksumTotals {
+    fn add(&mut self, name: &'static str, checksum: u64) -> u64 {
+        match self.0.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => *total = total.wrapping_add(checksum),
+            None => self.0.push((name, checksum)),
+        }
+        checksum
+    }
+}
+
+type PlayerState = (
+    Player,
+    Transform,
+    Option<ShipClass>,
+    Option<Speed>,
+    Option<Weapon>,
+    Option<Velocity>,
+    Option<Acceleration>,
+    Option<Throttle>,
+    Option<BoostFuel>,
+    Option<Health>,
+    Option<Shield>,
+    Option<(MissileAmmo, MissileReady, FlareAmmo, FlareCooldown)>,
+);
+type BulletState = (BulletId, BulletOwner, BirthFrame, Transform, Option<Damage>);
+type MissileState = (BulletId, Missile, BirthFrame, Transform, Damage);
+type FlareState = (BulletId, BirthFrame, Velocity, Transform);
+type AsteroidState = (AsteroidId, Asteroid, Transform, Health);
+
+/// Copies of the rollback state of one frame. Only a desynced frame's is
+/// ever formatted, so capturing one every frame stays cheap.
+struct FrameState {
+    frame: u32,
+    scores: Scores,
+    round_end_timer: RoundEndTimer,
+    next_bullet_id: NextBulletId,
+    arena: Arena,
+    asteroid_seed: AsteroidSeed,
+    next_asteroid_id: NextAsteroidId,
+    ship_choices: ShipChoices,
+    players: Vec<PlayerState>,
+    bullets: Vec<BulletState>,
+    missiles: Vec<MissileState>,
+    flares: Vec<FlareState>,
+    asteroids: Vec<AsteroidState>,
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn capture_frame_dump(
+    mut dumps: ResMut<FrameDumps>,
+    frame: Res<SimulationFrame>,
+    scores: Res<Scores>,
+    round_end_timer: Res<RoundEndTimer>,
+    next_bullet_id: Res<NextBulletId>,
+    arena: Res<Arena>,
+    asteroid_seed: Res<AsteroidSeed>,
+    next_asteroid_id: Res<NextAsteroidId>,
+    ship_choices: Res<ShipChoices>,
+    players: Query<(&Player, &Transform, Option<&ShipClass>, Option<&Speed>, Option<&Weapon>, Option<&Velocity>, Option<&Acceleration>, Option<&Throttle>, Option<&BoostFuel>, Option<&Health>, Option<&Shield>, Option<(&MissileAmmo, &MissileReady, &FlareAmmo, &FlareCooldown)>), Without<Bullet>>,
+    bullets: Query<(&BulletId, &BulletOwner, &BirthFrame, &Transform, Option<&Damage>), With<Bullet>>,
+    missiles: Query<(&BulletId, &Missile, &BirthFrame, &Transform, &Damage)>,
+    flares: Query<(&BulletId, &BirthFrame, &Velocity, &Transform), With<Flare>>,
+    asteroids: Query<(&AsteroidId, &Asteroid, &Transform, &Health)>,
+) {
+    dumps.insert(FrameState {
+        frame: frame.0,
+        scores: scores.clone(),
+        round_end_timer: round_end_timer.clone(),
+        next_bullet_id: *next_bullet_id,
+        arena: *arena,
+        asteroid_seed: *asteroid_seed,
+        next_asteroid_id: *next_asteroid_id,
+        ship_choices: ship_choices.clone(),
+        players: players
+            .iter()
+            .map(|(player, transform, ship_class, speed, weapon, velocity, acceleration, throttle, boost_fuel, health, shield, secondaries)| {
+                (
+                    *player,
+                    *transform,
+                    ship_class.copied(),
+                    speed.copied(),
+                    weapon.copied(),
+                    velocity.copied(),
+                    acceleration.copied(),
+                    throttle.copied(),
+                    boost_fuel.copied(),
+                    health.copied(),
+                    shield.copied(),
+                    secondaries.map(|(missile_ammo, missile_ready, flare_ammo, flare_cooldown)| {
+                        (*missile_ammo, *missile_ready, *flare_ammo, *flare_cooldown)
+                    }),
+                )
+            })
+            .collect(),
+        bullets: bullets
+            .iter()
+            .map(|(bullet_id, owner, birth_frame, transform, damage)| (*bullet_id, *owner, *birth_frame, *transform, damage.copied()))
+            .collect(),
+        missiles: missiles
+            .iter()
+            .map(|(missile_id, missile, birth_frame, transform, damage)| (*missile_id, *missile, *birth_frame, *transform, *damage))
+            .collect(),
+        flares: flares
+            .iter()
+            .map(|(flare_id, birth_frame, velocity, transform)| (*flare_id, *birth_frame, *velocity, *transform))
+            .collect(),
+        asteroids: asteroids
+            .iter()
+            .map(|(asteroid_id, asteroid, transform, health)| (*asteroid_id, *asteroid, *transform, *health))
+            .collect(),
+    });
+}
+
+impl FrameState {
+    /// Every resource and component with its checksum, one per line
+    fn format(&self) -> String {
+        let mut totals = ChecksumTotals::default();
+        let mut out = String::new();
+        let _ = writeln!(out, "frame {}", self.frame);
+
+        let frame = SimulationFrame(self.frame);
+        let _ = writeln!(out, "resource SimulationFrame {:?} checksum {:016X}", frame.0, checksum_simulation_frame(&frame));
+        let _ = writeln!(out, "resource Scores {:?} checksum {:016X}", self.scores.0, checksum_scores(&self.scores));
+        let _ = writeln!(
+            out,
+            "resource RoundEndTimer elapsed {:?} checksum {:016X}",
+            self.round_end_timer.elapsed(),
+            checksum_round_end_timer(&self.round_end_timer),
+        );
+        let _ = writeln!(out, "resource NextBulletId {:?} checksum {:016X}", self.next_bullet_id, checksum_next_bullet_id(&self.next_bullet_id));
+        let _ = writeln!(out, "resource Arena {:?} checksum {:016X}", self.arena, checksum_arena(&self.arena));
+        let _ = writeln!(out, "resource AsteroidSeed {:?} checksum {:016X}", self.asteroid_seed.0, checksum_asteroid_seed(&self.asteroid_seed));
+        let _ = writeln!(out, "resource NextAsteroidId {:?} checksum {:016X}", self.next_asteroid_id, checksum_next_asteroid_id(&self.next_asteroid_id));
+        let _ = writeln!(out, "resource ShipChoices {:?} checksum {:016X}", self.ship_choices.0, checksum_ship_choices(&self.ship_choices));
+
+        // entity ids differ between peers, so order entities by their stable ids
+        let mut players: Vec<_> = self.players.iter().collect();
+        players.sort_by_key(|(player, ..)| player.handle);
+        for (player, transform, ship_class, speed, weapon, velocity, acceleration, throttle, boost_fuel, health, shield, secondaries) in players {
+            let name = format!("player {}", player.handle);
+            let _ = writeln!(out, "{name} Player checksum {:016X}", totals.add("Player", checksum_player(player)));
+            let _ = writeln!(
+                out,
+                "{name} Transform {:?} {:?} checksum {:016X}",
+                transform.translation,
+                transform.rotation,
+                totals.add("Transform", checksum_transform(transform)),
+            );
+            if let Some(ship_class) = ship_class {
+                let _ = writeln!(out, "{name} ShipClass {} checksum {:016X}", ship_class.0, totals.add("ShipClass", checksum_ship_class(ship_class)));
+            }
+            if let Some(speed) = speed {
+                let _ = writeln!(out, "{name} Speed {} checksum {:016X}", speed.0, totals.add("Speed", checksum_speed(speed)));
+            }
+            if let Some(weapon) = weapon {
+                let _ = writeln!(
+                    out,
+                    "{name} Weapon cooldown {} heat {} overheated {} checksum {:016X}",
+                    weapon.cooldown,
+                    weapon.heat,
+                    weapon.overheated,
+                    totals.add("Weapon", checksum_weapon(weapon)),
+                );
+            }
+            if let Some(velocity) = velocity {
+                let _ = writeln!(out, "{name} Velocity {:?} checksum {:016X}", velocity.0, totals.add("Velocity", checksum_velocity(velocity)));
+            }
+            if let Some(acceleration) = acceleration {
+                let _ = writeln!(out, "{name} Acceleration {:?} checksum {:016X}", acceleration.0, totals.add("Acceleration", checksum_acceleration(acceleration)));
+            }
+            if let Some(throttle) = throttle {
+                let _ = writeln!(out, "{name} Throttle {} checksum {:016X}", throttle.0, totals.add("Throttle", checksum_throttle(throttle)));
+            }
+            if let Some(boost_fuel) = boost_fuel {
+                let _ = writeln!(out, "{name} BoostFuel {} checksum {:016X}", boost_fuel.0, totals.add("BoostFuel", checksum_boost_fuel(boost_fuel)));
+            }
+            if let Some(health) = health {
+                let _ = writeln!(out, "{name} Health {} checksum {:016X}", health.0, totals.add("Health", checksum_health(health)));
+            }
+            if let Some(shield) = shield {
+                let _ = writeln!(
+                    out,
+                    "{name} Shield {} last hit {} checksum {:016X}",
+                    shield.charge,
+                    shield.last_hit_frame,
+                    totals.add("Shield", checksum_shield(shield)),
+                );
+            }
+            if let Some((missile_ammo, missile_ready, flare_ammo, flare_cooldown)) = secondaries {
+                let _ = writeln!(out, "{name} MissileAmmo {} checksum {:016X}", missile_ammo.0, totals.add("MissileAmmo", checksum_missile_ammo(missile_ammo)));
+                let _ = writeln!(out, "{name} MissileReady {} checksum {:016X}", missile_ready.0, totals.add("MissileReady", checksum_missile_ready(missile_ready)));
+                let _ = writeln!(out, "{name} FlareAmmo {} checksum {:016X}", flare_ammo.0, totals.add("FlareAmmo", checksum_flare_ammo(flare_ammo)));
+                let _ = writeln!(out, "{name} FlareCooldown {} checksum {:016X}", flare_cooldown.0, totals.add("FlareCooldown", checksum_flare_cooldown(flare_cooldown)));
+            }
+        }
+
+        let mut bullets: Vec<_> = self.bullets.iter().collect();
+        bullets.sort_by_key(|(bullet_id, ..)| bullet_id.0);
+        for (bullet_id, owner, birth_frame, transform, damage) in bullets {
+            let name = format!("bullet {}", bullet_id.0);
+            let _ = writeln!(out, "{name} BulletOwner {} checksum {:016X}", owner.handle, totals.add("BulletOwner", checksum_bullet_owner(owner)));
+            let _ = writeln!(out, "{name} BirthFrame {} checksum {:016X}", birth_frame.0, totals.add("BirthFrame", checksum_birth_frame(birth_frame)));
+            let _ = writeln!(
+                out,
+                "{name} Transform {:?} {:?} checksum {:016X}",
+                transform.translation,
+                transform.rotation,
+                totals.add("Transform", checksum_transform(transform)),
+            );
+            if let Some(damage) = damage {
+                let _ = writeln!(out, "{name} Damage {} checksum {:016X}", damage.0, totals.add("Damage", checksum_damage(damage)));
+            }
+        }
+
+        let mut missiles: Vec<_> = self.missiles.iter().collect();
+        missiles.sort_by_key(|(missile_id, ..)| missile_id.0);
+        for (missile_id, missile, birth_frame, transform, damage) in missiles {
+            let name = format!("missile {}", missile_id.0);
+            let _ = writeln!(
+                out,
+                "{name} Missile owner {} target {:?} checksum {:016X}",
+                missile.owner,
+                missile.target,
+                totals.add("Missile", checksum_missile(missile)),
+            );
+            let _ = writeln!(out, "{name} BirthFrame {} checksum {:016X}", birth_frame.0, totals.add("BirthFrame", checksum_birth_frame(birth_frame)));
+            let _ = writeln!(
+                out,
+                "{name} Transform {:?} {:?} checksum {:016X}",
+                transform.translation,
+                transform.rotation,
+                totals.add("Transform", checksum_transform(transform)),
+            );
+            let _ = writeln!(out, "{name} Damage {} checksum {:016X}", damage.0, totals.add("Damage", checksum_damage(damage)));
+        }
+
+        let mut flares: Vec<_> = self.flares.iter().collect();
+        flares.sort_by_key(|(flare_id, ..)| flare_id.0);
+        for (flare_id, birth_frame, velocity, transform) in flares {
+            let name = format!("flare {}", flare_id.0);
+            let _ = writeln!(out, "{name} BirthFrame {} checksum {:016X}", birth_frame.0, totals.add("BirthFrame", checksum_birth_frame(birth_frame)));
+            let _ = writeln!(out, "{name} Velocity {:?} checksum {:016X}", velocity.0, totals.add("Velocity", checksum_velocity(velocity)));
+            let _ = writeln!(
+                out,
+                "{name} Transform {:?} {:?} checksum {:016X}",
+                transform.translation,
+                transform.rotation,
+                totals.add("Transform", checksum_transform(transform)),
+            );
+        }
+
+        let mut asteroids: Vec<_> = self.asteroids.iter().collect();
+        asteroids.sort_by_key(|(asteroid_id, ..)| asteroid_id.0);
+        for (asteroid_id, asteroid, transform, health) in asteroids {
+            let name = format!("asteroid {}", asteroid_id.0);
+            let _ = writeln!(out, "{name} Asteroid radius {} checksum {:016X}", asteroid.radius, totals.add("Asteroid", checksum_asteroid(asteroid)));
+            let _ = writeln!(out, "{name} Health {} checksum {:016X}", health.0, totals.add("Health", checksum_health(health)));
+            let _ = writeln!(
+                out,
+                "{name} Transform {:?} {:?} checksum {:016X}",
+                transform.translation,
+                transform.rotation,
+                totals.add("Transform", checksum_transform(transform)),
+            );
+        }
+
+        for (name, total) in &totals.0 {
+            let _ = writeln!(out, "total {name} checksum {total:016X}");
+        }
+
+        out
+    }
+}
+
+/// Writes the dump of a desynced frame to disk, named after the frame and
+/// the local player so the files from both peers can be diffed
+pub fn write_desync_dump(dumps: &FrameDumps, frame: i32, local_handles: &[usize]) {
+    let Some(state) = u32::try_from(frame).ok().and_then(|frame| dumps.get(frame)) else {
+        warn!("no state dump kept for frame {frame}");
+        return;
+    };
+    let dump = state.format();
+    let handles = local_handles
+        .iter()
+        .map(|handle| handle.to_string())
+        .collect::<Vec<_>>()
+        .join("_");
+    let path = format!("desync_frame{frame}_player{handles}.txt");
+    match std::fs::write(&path, &dump) {
+        Ok(()) => error!("wrote state of desynced frame {frame} to {path}"),
+        // no file system in the browser, so fall back to the log
+        Err(e) => error!("failed to write {path}: {e}\n{dump}"),
+    }
+}
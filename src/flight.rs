@@ -0,0 +1,95 @@
+use crate::arena::Arena;
+use crate::components::*;
+use crate::input::*;
+use bevy::prelude::*;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Time to go from idle to full throttle
+const THROTTLE_SECONDS: f32 = 2.0;
+/// Thrust per unit of top speed, which is also the drag of arcade flight
+const THRUST_PER_SPEED: f32 = 1.0;
+const BOOST_THRUST_FACTOR: f32 = 3.0;
+const BOOST_SPEED_FACTOR: f32 = 2.0;
+/// Seconds of boost on a full tank
+pub const BOOST_FUEL_SECONDS: f32 = 3.0;
+/// Seconds of boost regained per second of not boosting
+const BOOST_RECHARGE_RATE: f32 = 0.5;
+
+/// How ships carry their momentum. Every peer has to fly with the same model.
+#[derive(Resource, ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlightAssist {
+    /// Drag slows ships down and their velocity is steered back towards the
+    /// nose, so they fly where they point
+    #[default]
+    Arcade,
+    /// Velocity only changes with thrust, so ships keep drifting after turning
+    Newtonian,
+}
+
+impl FlightAssist {
+    fn drag(self) -> f32 {
+        match self {
+            FlightAssist::Arcade => THRUST_PER_SPEED,
+            FlightAssist::Newtonian => 0.0,
+        }
+    }
+
+    /// How quickly velocity is turned towards the nose, per second
+    fn alignment(self) -> f32 {
+        match self {
+            FlightAssist::Arcade => 3.0,
+            FlightAssist::Newtonian => 0.0,
+        }
+    }
+}
+
+/// Integrates one step of a ship's flight: throttle from input, thrust along
+/// the nose into [`Acceleration`], acceleration into [`Velocity`] and velocity
+/// into the translation. Boost kicks in when throttle up is held at full
+/// throttle, and bounded arenas push ships that stray outside back in.
+#[allow(clippy::too_many_arguments)]
+pub fn fly(
+    assist: FlightAssist,
+    arena: Arena,
+    input: [u8; 3],
+    speed: &Speed,
+    transform: &mut Transform,
+    velocity: &mut Velocity,
+    acceleration: &mut Acceleration,
+    throttle: &mut Throttle,
+    boost_fuel: &mut BoostFuel,
+    dt: f32,
+) {
+    let throttle_input = throttle_input(input);
+    let boosting = throttle_input > 0.0 && throttle.0 >= 1.0 && boost_fuel.0 > 0.0;
+    throttle.0 = (throttle.0 + throttle_input * dt / THROTTLE_SECONDS).clamp(0.0, 1.0);
+
+    let mut thrust = throttle.0 * speed.0 * THRUST_PER_SPEED;
+    let mut max_speed = speed.0;
+    if boosting {
+        thrust *= BOOST_THRUST_FACTOR;
+        max_speed *= BOOST_SPEED_FACTOR;
+        boost_fuel.0 = (boost_fuel.0 - dt).max(0.0);
+    } else {
+        boost_fuel.0 = (boost_fuel.0 + BOOST_RECHARGE_RATE * dt).min(BOOST_FUEL_SECONDS);
+    }
+
+    let nose = transform.rotation.mul_vec3(Vec3::Z);
+    acceleration.0 = nose * thrust - velocity.0 * assist.drag();
+    velocity.0 += acceleration.0 * dt;
+
+    let alignment = (assist.alignment() * dt).min(1.0);
+    if alignment > 0.0 {
+        velocity.0 = velocity.0.lerp(nose * velocity.0.length(), alignment);
+    }
+
+    // after alignment, which would otherwise steer ships straight back out
+    let push = arena.boundary_acceleration(transform.translation);
+    acceleration.0 += push;
+    velocity.0 += push * dt;
+    velocity.0 = velocity.0.clamp_length_max(max_speed);
+
+    transform.translation += velocity.0 * dt;
+    transform.translation = arena.wrap(transform.translation);
+}
@@ -25,6 +25,7 @@ use bevy::ui::Style;
 use bevy::ui::UiRect;
 use bevy::ui::Val;
 use bevy::ui::ZIndex;
+use crate::config::GameConfig;
 
 /// Marker to find the container entity so we can show/hide the FPS counter
 #[derive(Component)]
@@ -47,6 +48,7 @@ impl Plugin for FpsPlugin {
 
 fn setup_fps_counter(
     mut commands: Commands,
+    game_config: Res<GameConfig>,
 ) {
     // create our UI root node
     // this is the wrapper/container for the text
@@ -72,6 +74,12 @@ fn setup_fps_counter(
                 padding: UiRect::all(Val::Px(4.0)),
                 ..Default::default()
             },
+            // F12 toggles it later on
+            visibility: if game_config.show_fps {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            },
             ..Default::default()
         },
     )).id();
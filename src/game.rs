@@ -1,5 +1,5 @@
-use crate::{args::Args, fps_plugin::FpsPlugin, pbr_material::CustomStandardMaterial, radar::RadarPlugin};
-use bevy::{prelude::*, scene::SceneInstance, utils::HashMap};
+use crate::{arena::Arena, args::Args, asteroids::field_seed, bots::*, config::*, desync::*, flight::FlightAssist, fps_plugin::FpsPlugin, gameplay::*, pbr_material::CustomStandardMaterial, presentation::{PresentationPlugin, Viewpoint}, radar::RadarPlugin, replay::*, ships::*, spectator::*};
+use bevy::{core_pipeline::clear_color::ClearColorConfig, prelude::*, scene::SceneInstance, utils::{HashMap, HashSet}};
 use bevy_asset_loader::prelude::*;
 use bevy_egui::{
     egui::{self, Align2, Color32, FontId, RichText},
@@ -7,22 +7,14 @@ use bevy_egui::{
 };
 use bevy_ggrs::{ggrs::DesyncDetection, prelude::*, *};
 use bevy_matchbox::prelude::*;
-use bevy_roll_safe::prelude::*;
 use clap::Parser;
+use serde::{Deserialize, Serialize};
 use crate::components::*;
 use crate::input::*;
 use bevy_prototype_lyon::prelude::*;
 use virtual_joystick::*;
 use bevy_round_ui::prelude::*;
 
-const SHIP_SPEED: f32 = 50.0;
-
-// The first generic parameter, u8, is the input type: 4-directions + fire fits
-// easily in a single byte
-// The second parameter is the address type of peers: Matchbox' WebRtcSocket
-// addresses are called `PeerId`s
-pub type Config = bevy_ggrs::GgrsConfig<[u8; 3], PeerId>;
-
 #[derive(States, Clone, Eq, PartialEq, Debug, Hash, Default)]
 enum GameState {
     #[default]
@@ -31,32 +23,6 @@ enum GameState {
     InGame,
 }
 
-#[derive(States, Clone, Eq, PartialEq, Debug, Hash, Default)]
-enum RollbackState {
-    /// When the characters running and gunning
-    #[default]
-    InRound,
-    /// When one character is dead, and we're transitioning to the next round
-    RoundEnd,
-}
-
-#[derive(Resource, Clone, Deref, DerefMut)]
-struct RoundEndTimer(Timer);
-
-#[derive(Resource, Default, Clone, Copy, Debug)]
-struct Scores(u32, u32);
-
-impl Default for RoundEndTimer {
-    fn default() -> Self {
-        RoundEndTimer(Timer::from_seconds(1.0, TimerMode::Repeating))
-    }
-}
-
-#[derive(Resource, Debug, Clone)]
-pub struct GameConfig {
-    pub room_url: String,
-}
-
 use wasm_bindgen::prelude::wasm_bindgen;
 
 #[wasm_bindgen(inline_js =
@@ -77,44 +43,56 @@ use wasm_bindgen::prelude::wasm_bindgen;
     "
 )]
 extern "C" {
-    fn url_params() -> Vec<String>;
+    pub(crate) fn url_params() -> Vec<String>;
     fn go_fullscreen();
 }
 
 pub fn run_game() {
     let args = Args::parse();
-    info!("Args: {args:?}");
+    let mut log = StartupLog::default();
+    log.info(format!("Args: {args:?}"));
 
-    let default_room_url = "wss://dune-breezy-honeysuckle.glitch.me/?next=2";
+    let mut game_config = GameConfig::load(&args, &mut log);
 
-    #[allow(unused_mut)]
-    let mut game_config = GameConfig {
-        room_url: default_room_url.into(),
-    };
+    let replay = args.replay.as_ref().map(|path| {
+        ReplayPlayback::load(path).unwrap_or_else(|e| panic!("failed to load replay {path}: {e}"))
+    });
 
-    #[cfg(target_family = "wasm")]
-    {
-        let url_params2 = url_params();
-        for x in url_params2 {
-            let y: Vec<&str> = x.split(",").collect();
-            if y.len() != 2 {
-                continue;
-            }
-            if y[0] == "room_url" {
-                game_config.room_url = y[1].into();
-            }
-        }
+    if let Some(replay) = &replay {
+        game_config.num_players = replay.header.num_players.clamp(1, MAX_PLAYERS);
+        game_config.flight_assist = replay.header.flight_assist;
     }
+    let arena = replay.as_ref().map_or(Arena::new(args.arena, args.arena_size), |replay| replay.header.arena);
 
-    App::new()
+    if args.headless {
+        log.info(format!("{game_config:?}"));
+        crate::headless::run_headless(args, game_config, replay, log);
+        return;
+    }
+
+    if game_config.spectate && game_config.local_players > 1 {
+        log.warn("spectators can't split the screen".to_string());
+        game_config.local_players = 1;
+    }
+    log.info(format!("{game_config:?}"));
+    // there's no way to matchmake several pilots on one machine, so sharing
+    // the screen means practice unless there's a local session already
+    let practice = (args.offline || game_config.local_players > 1) && !args.synctest && replay.is_none();
+
+    let mut app = App::new();
+    app
+        .insert_resource(game_config.flight_assist)
+        .insert_resource(arena)
         .insert_resource(args)
         .insert_resource(game_config)
+        .insert_resource(log)
+        .add_systems(Startup, flush_startup_log)
         .add_state::<GameState>()
         .add_loading_state(
             LoadingState::new(GameState::AssetLoading).continue_to_state(GameState::Matchmaking),
         )
         .add_collection_to_loading_state::<_, ImageAssets>(GameState::AssetLoading)
-        .add_collection_to_loading_state::<_, ModelAssets>(GameState::AssetLoading)
+        .add_collection_to_loading_state::<_, ShipDefAssets>(GameState::AssetLoading)
         .add_plugins((
             DefaultPlugins.set(WindowPlugin {
                 primary_window: Some(Window {
@@ -124,35 +102,34 @@ pub fn run_game() {
                 }),
                 ..default()
             }),
-            GgrsPlugin::<Config>::default(),
+            GameplayPlugin,
+            ReplayPlugin,
+            DesyncPlugin,
+            PresentationPlugin,
             EguiPlugin,
             ShapePlugin,
             VirtualJoystickPlugin::<String>::default(),
             RoundUiPlugin,
             FpsPlugin,
             RadarPlugin,
+            SpectatorPlugin,
             MaterialPlugin::<CustomStandardMaterial>::default(),
+            ShipsPlugin,
         ))
         .init_resource::<ButtonStyle>()
-        .add_ggrs_state::<RollbackState>()
-        .rollback_resource_with_clone::<RoundEndTimer>()
-        .rollback_resource_with_copy::<Scores>()
-        .rollback_component_with_clone::<Transform>()
-        .rollback_component_with_copy::<BulletReady>()
-        .rollback_component_with_copy::<BirthTime>()
-        .rollback_component_with_copy::<Player>()
-        .rollback_component_with_copy::<Velocity>()
-        .rollback_component_with_copy::<Acceleration>()
-        .checksum_component::<Transform>(checksum_transform)
+        .init_resource::<GamepadPilots>()
         .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
-        .init_resource::<RoundEndTimer>()
-        .init_resource::<Scores>()
         //
         .add_systems(
             OnEnter(GameState::Matchmaking),
             (
-                setup,
-                start_matchbox_socket,
+                load_ship_defs,
+                (
+                    setup,
+                    start_matchbox_socket.run_if(p2p_mode),
+                    start_synctest_session.run_if(local_session_mode),
+                )
+                    .after(load_ship_defs),
             ),
         )
         .insert_resource(LocalInputs::<Config>(HashMap::from_iter(vec![(0, [0, 0, 0])].drain(0..))))
@@ -166,51 +143,53 @@ pub fn run_game() {
                 swap_standard_material,
                 customize_scene_materials,
                 button_system,
+                assign_gamepads,
             ),
         )
         .add_systems(
             Update,
             (
                 (
-                    camera_follow.after(move_players),
-                    move_skybox_with_camera.after(camera_follow),
                     move_players,
-                    reload_bullet,
-                    fire_bullets.after(move_players).after(reload_bullet),
+                    cool_weapons,
+                    fire_bullets.after(move_players).after(cool_weapons),
                     move_bullet.after(fire_bullets),
-                    wait_for_players,
+                    tick_simulation_frame.after(move_bullet),
+                    pick_ship.run_if(resource_exists::<Lobby>()).run_if(not(spectate_mode)),
+                    practice_button.run_if(resource_exists::<Lobby>()).run_if(not(spectate_mode)),
+                    wait_for_players.run_if(p2p_mode).run_if(not(spectate_mode)).after(pick_ship).after(practice_button),
+                    wait_for_match.run_if(p2p_mode).run_if(spectate_mode),
+                    start_practice_session.run_if(resource_exists::<Practice>()),
+                    show_lobby_problem.run_if(resource_exists::<Lobby>()),
                 ).run_if(in_state(GameState::Matchmaking)),
                 (
                     handle_ggrs_events,
                     update_score_ui,
+                    update_health_hud,
+                    turn_away_late_spectators
+                        .run_if(resource_exists::<Lobby>())
+                        .run_if(resource_exists::<MatchboxSocket<MultipleChannels>>()),
                 ).run_if(in_state(GameState::InGame)),
             ),
         )
-        .add_systems(ReadInputs, read_local_inputs)
-        .add_systems(Update, handle_button_interactions)
-        .add_systems(OnEnter(RollbackState::InRound), spawn_players)
         .add_systems(
-            GgrsSchedule,
+            ReadInputs,
             (
-                camera_follow.after(move_players).after(move_bullet).ambiguous_with(kill_players),
-                move_skybox_with_camera.after(camera_follow).ambiguous_with(kill_players),
-                move_players,
-                reload_bullet,
-                fire_bullets.after(move_players).after(reload_bullet),
-                move_bullet.after(fire_bullets),
-                kill_players.after(move_bullet).after(move_players),
-            )
-                .run_if(in_state(RollbackState::InRound))
-                .after(apply_state_transition::<RollbackState>),
-        )
-        .add_systems(
-            GgrsSchedule,
-            round_end_timeout
-                .run_if(in_state(RollbackState::RoundEnd))
-                .ambiguous_with(kill_players)
-                .after(apply_state_transition::<RollbackState>),
+                read_local_inputs.run_if(not(resource_exists::<ReplayPlayback>())),
+                drive_bots.after(read_local_inputs).run_if(resource_exists::<Bots>()),
+            ),
         )
-        .run();
+        .add_systems(Update, handle_button_interactions)
+        .add_systems(OnEnter(RollbackState::InRound), hide_awaiting_players);
+
+    if practice {
+        app.insert_resource(Practice);
+    }
+    if let Some(replay) = replay {
+        app.insert_resource(replay);
+    }
+
+    app.run();
 }
 
 /// Resource containing material handles for the different button states
@@ -291,7 +270,8 @@ impl FromWorld for ButtonStyle {
 #[derive(Component, Debug, PartialEq, Eq)]
 pub enum ButtonAction {
     Fire,
-    //Thrust,
+    Thrust,
+    Missile,
 }
 
 /// Marker component to identify round buttons
@@ -304,39 +284,95 @@ struct ImageAssets {
     sky: Handle<Image>,
 }
 
-#[derive(AssetCollection, Resource)]
-struct ModelAssets {
-    #[asset(path = "low_poly_x-wing.glb#Scene0")]
-    xwing: Handle<Scene>,
+/// Scene of every ship class, indexed like [`ShipDefs`]
+#[derive(Resource)]
+pub struct ModelAssets {
+    pub ships: Vec<Handle<Scene>>,
 }
 
 #[derive(Resource)]
-struct ModelAssets2 {
-    bullet_mesh: Handle<Mesh>,
-    bullet_material: Handle<CustomStandardMaterial>,
+pub struct ModelAssets2 {
+    pub bullet_mesh: Handle<Mesh>,
+    pub bullet_material: Handle<CustomStandardMaterial>,
+    /// unit sphere, scaled up to each asteroid's radius
+    pub asteroid_mesh: Handle<Mesh>,
+    pub asteroid_material: Handle<CustomStandardMaterial>,
+    pub missile_mesh: Handle<Mesh>,
+    pub missile_material: Handle<CustomStandardMaterial>,
+    pub flare_mesh: Handle<Mesh>,
+    pub flare_material: Handle<CustomStandardMaterial>,
+}
+
+/// Gathers up the loaded ship definitions, and starts loading their models
+fn load_ship_defs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    collection: Res<ShipDefAssets>,
+    assets: Res<Assets<ShipDef>>,
+    mut game_config: ResMut<GameConfig>,
+) {
+    let ship_defs = ShipDefs::from_assets(&collection, &assets);
+    // settle on a ship that exists once, rather than every time it's looked up
+    game_config.ship = Some(ship_defs.name_of(game_config.ship.as_deref()));
+    commands.insert_resource(ModelAssets {
+        ships: ship_defs.0.iter().map(|def| asset_server.load(def.model.clone())).collect(),
+    });
+    commands.insert_resource(ship_defs);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     button_style: Res<ButtonStyle>,
     images: Res<ImageAssets>,
-    models: Res<ModelAssets>,
+    ship_defs: Res<ShipDefs>,
+    game_config: Res<GameConfig>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<CustomStandardMaterial>>,
 ) {
     let camera_init_pos = Vec3::new(0.0, 3.0, -10.0);
-    // camera
-    commands.spawn(Camera3dBundle {
-        transform:
-            Transform::from_translation(camera_init_pos.clone())
-                .looking_at(Vec3::new(0.0, 0.0, 0.0), Vec3::Y),
-        projection: Projection::Perspective(PerspectiveProjection {
-            far: 100_000.0,
+    // a camera for each pilot at this machine
+    let split_screen = game_config.local_players > 1;
+    for pilot in 0..game_config.local_players {
+        commands.spawn((
+            Camera3dBundle {
+                transform:
+                    Transform::from_translation(camera_init_pos.clone())
+                        .looking_at(Vec3::new(0.0, 0.0, 0.0), Vec3::Y),
+                camera: Camera {
+                    order: pilot as isize,
+                    ..default()
+                },
+                camera_3d: Camera3d {
+                    // the first camera has already cleared the window
+                    clear_color: if pilot == 0 { ClearColorConfig::Default } else { ClearColorConfig::None },
+                    ..default()
+                },
+                projection: Projection::Perspective(PerspectiveProjection {
+                    far: 100_000.0,
+                    ..default()
+                }),
+                ..default()
+            },
+            PilotCamera(pilot),
+            UiCameraConfig { show_ui: !split_screen },
+        ));
+    }
+    // split-screen UI is laid out over the whole window, so it gets a camera
+    // of its own drawn over the others
+    if split_screen {
+        commands.spawn(Camera2dBundle {
+            camera: Camera {
+                order: MAX_LOCAL_PLAYERS as isize,
+                ..default()
+            },
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::None,
+            },
             ..default()
-        }),
-        ..default()
-    });
+        });
+    }
 
     // skybox
     let material = materials.add(CustomStandardMaterial {
@@ -413,39 +449,72 @@ fn setup(
                 Interaction::default(),
             ));
         });
-    
-    // load player to use while waiting for players
-    commands
-        .spawn((
-            Player { handle: 0 },
-            BulletReady(true),
-            Speed(SHIP_SPEED),
-            Acceleration(Vec3::ZERO),
-            Transform::IDENTITY,
-        ));
-    
-    // Player 1 3D Model
+
     commands
-        .spawn((
-            FollowPlayer { target_player_handle: 0, },
-            SceneBundle {
-                scene: models.xwing.clone(),
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Px(100.0),
+                height: Val::Px(100.0),
+                position_type: PositionType::Absolute,
+                right: Val::Percent(10.),
+                bottom: Val::Percent(10.0),
+                margin: UiRect::bottom(Val::Px(120.0)),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
                 ..default()
             },
-            CustomizeMaterial,
-        ));
-    
-    // Player 2 3D Model
+            ..default()
+        })
+        .with_children(|p| {
+            p.spawn((
+                MaterialNodeBundle {
+                    material: button_style.default_2.clone(),
+                    style: Style {
+                        width: Val::Px(100.),
+                        height: Val::Px(100.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                ButtonAction::Thrust,
+                Interaction::default(),
+            ));
+        });
+
     commands
-        .spawn((
-            FollowPlayer { target_player_handle: 1, },
-            SceneBundle {
-                scene: models.xwing.clone(),
-                visibility: Visibility::Hidden,
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Px(100.0),
+                height: Val::Px(100.0),
+                position_type: PositionType::Absolute,
+                right: Val::Percent(10.),
+                bottom: Val::Percent(10.0),
+                margin: UiRect::bottom(Val::Px(240.0)),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
                 ..default()
             },
-            CustomizeMaterial,
-        ));
+            ..default()
+        })
+        .with_children(|p| {
+            p.spawn((
+                MaterialNodeBundle {
+                    material: button_style.default_2.clone(),
+                    style: Style {
+                        width: Val::Px(100.),
+                        height: Val::Px(100.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                ButtonAction::Missile,
+                Interaction::default(),
+            ));
+        });
+    
+    // load player to use while waiting for players
+    let class = ship_defs.pick(game_config.ship.as_deref());
+    commands.spawn(new_ship(0, Transform::IDENTITY, class, ship_defs.get(class)));
     
     commands.spawn((
         AwaitingPlayersRoot,
@@ -484,9 +553,41 @@ fn setup(
         ..default()
     });
 
+    // asteroid
+    let asteroid_mesh = meshes.add(
+        Mesh::try_from(shape::Icosphere { radius: 1.0, subdivisions: 2 }).expect("icosphere mesh"),
+    );
+    let asteroid_material = materials.add(CustomStandardMaterial {
+        base_color: Color::rgb(0.4, 0.37, 0.33),
+        ..default()
+    });
+
+    // missile
+    let missile_mesh = meshes.add(shape::Box::new(0.5, 0.5, 3.0).into());
+    let missile_material = materials.add(CustomStandardMaterial {
+        base_color: Color::ORANGE,
+        ..default()
+    });
+
+    // flare
+    let flare_mesh = meshes.add(
+        Mesh::try_from(shape::Icosphere { radius: 0.6, subdivisions: 1 }).expect("icosphere mesh"),
+    );
+    let flare_material = materials.add(CustomStandardMaterial {
+        base_color: Color::YELLOW,
+        unlit: true,
+        ..default()
+    });
+
     commands.insert_resource(ModelAssets2 {
         bullet_mesh,
         bullet_material,
+        asteroid_mesh,
+        asteroid_material,
+        missile_mesh,
+        missile_material,
+        flare_mesh,
+        flare_material,
     });
 
     // fullscreen button
@@ -563,17 +664,6 @@ fn button_system(
     }
 }
 
-fn move_skybox_with_camera(
-    camera: Query<&Transform, (With<Camera>,Without<Skybox>)>,
-    mut skybox: Query<&mut Transform,With<Skybox>>,
-) {
-    for mut skybox_transform in &mut skybox {
-        for camera_transform in &camera {
-            skybox_transform.translation = camera_transform.translation;
-        }
-    }
-}
-
 fn swap_standard_material(
     mut commands: Commands,
     mut material_events: EventReader<AssetEvent<StandardMaterial>>,
@@ -631,7 +721,7 @@ fn swap_standard_material(
 }
 
 #[derive(Component)]
-struct CustomizeMaterial;
+pub struct CustomizeMaterial;
 
 fn customize_scene_materials(
     unloaded_instances: Query<(Entity, &SceneInstance), With<CustomizeMaterial>>,
@@ -703,63 +793,235 @@ fn handle_button_interactions(
     }
 }
 
-fn spawn_players(
-    mut commands: Commands,
+fn hide_awaiting_players(
     mut awaiting_players: Query<&mut Visibility, With<AwaitingPlayersRoot>>,
-    players: Query<Entity, With<Player>>,
-    bullets: Query<Entity, With<Bullet>>,
 ) {
-    info!("Spawning players");
-
     for mut awaiting_player_visibility in &mut awaiting_players {
         *awaiting_player_visibility = Visibility::Hidden;
     }
+}
 
-    for player in &players {
-        commands.entity(player).despawn_recursive();
+/// Synctest and replay sessions run with only local players
+fn local_session_mode(args: Res<Args>) -> bool {
+    args.synctest || args.replay.is_some()
+}
+
+fn p2p_mode(args: Res<Args>, practice: Option<Res<Practice>>) -> bool {
+    !args.synctest && args.replay.is_none() && practice.is_none()
+}
+
+/// Online, but only to watch
+fn spectate_mode(game_config: Res<GameConfig>) -> bool {
+    game_config.spectate
+}
+
+/// Target drones flying alongside the local pilot in practice
+const PRACTICE_DRONES: usize = 3;
+
+/// Fly a practice match rather than matchmaking, chosen with `--offline` or
+/// from the button shown while waiting for players
+#[derive(Resource)]
+struct Practice;
+
+/// Flies the local pilots against target drones, and any bots asked for,
+/// through a local session that never rolls back
+fn start_practice_session(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    args: Res<Args>,
+    mut game_config: ResMut<GameConfig>,
+    ship_defs: Res<ShipDefs>,
+    arena: Res<Arena>,
+) {
+    info!("Starting practice session");
+
+    let pilots = game_config.local_players;
+    let bots = game_config.bots.min(MAX_PLAYERS - pilots);
+    let drones = PRACTICE_DRONES.min(MAX_PLAYERS - pilots - bots);
+    let num_players = pilots + bots + drones;
+    game_config.num_players = num_players;
+    // there's no one to lag behind
+    let input_delay = 0;
+
+    let mut ships = vec![ship_defs.name_of(game_config.ship.as_deref()); pilots];
+    ships.resize(num_players, ship_defs.name_of(None));
+    let choices = ship_defs.choices(&ships).expect("every ship flown is one we have");
+    reset_simulation(&mut commands, num_players, args.seed, choices);
+    commands.insert_resource(new_synctest_session(num_players, input_delay, 0));
+    start_recording(&mut commands, &args, num_players, input_delay, args.seed, game_config.flight_assist, *arena, &ships);
+    commands.insert_resource(
+        Bots::new(pilots..pilots + bots, game_config.bot_difficulty).with(pilots + bots..num_players, BotDifficulty::Drone),
+    );
+    next_state.set(GameState::InGame);
+}
+
+/// Gives up on matchmaking for a practice match
+fn practice_button(mut commands: Commands, mut contexts: EguiContexts) {
+    let mut clicked = false;
+    egui::Area::new("practice")
+        .anchor(Align2::CENTER_BOTTOM, (0., -25.))
+        .show(contexts.ctx_mut(), |ui| {
+            clicked = ui.button("Practice offline").clicked();
+        });
+    if clicked {
+        commands.remove_resource::<MatchboxSocket<MultipleChannels>>();
+        commands.remove_resource::<Lobby>();
+        commands.insert_resource(Practice);
     }
+}
 
-    for bullet in &bullets {
-        commands.entity(bullet).despawn_recursive();
+fn start_synctest_session(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    args: Res<Args>,
+    game_config: Res<GameConfig>,
+    ship_defs: Res<ShipDefs>,
+    arena: Res<Arena>,
+    replay: Option<Res<ReplayPlayback>>,
+) {
+    // replayed inputs were recorded after the delay was applied
+    let input_delay = if replay.is_some() {
+        info!("Starting replay session");
+        0
+    } else {
+        info!("Starting synctest session");
+        game_config.input_delay
+    };
+
+    let num_players = game_config.num_players;
+    let asteroid_seed = replay.as_ref().map_or(args.seed, |replay| replay.header.asteroid_seed);
+    // every local pilot flies the configured ship
+    let ships = replay.as_ref().map_or_else(
+        || vec![ship_defs.name_of(game_config.ship.as_deref()); num_players],
+        |replay| replay.header.ships.clone(),
+    );
+    // a replay could come from a build with other ships
+    let choices = ship_defs.choices(&ships).unwrap_or_else(|e| panic!("can't fly the recorded ships: {e}"));
+    reset_simulation(&mut commands, num_players, asteroid_seed, choices);
+    commands.insert_resource(new_synctest_session(num_players, input_delay, args.check_distance));
+    start_recording(&mut commands, &args, num_players, input_delay, asteroid_seed, game_config.flight_assist, *arena, &ships);
+    // the last slots are flown by bots, the replay already has their inputs
+    if replay.is_none() && game_config.bots > 0 {
+        commands.insert_resource(Bots::new(num_players - game_config.bots..num_players, game_config.bot_difficulty));
     }
+    next_state.set(GameState::InGame);
+}
 
-    // Player 1
-    commands
-        .spawn((
-            Player { handle: 0 },
-            BulletReady(true),
-            Speed(SHIP_SPEED),
-            Acceleration(Vec3::ZERO),
-            Transform::IDENTITY,
-        ))
-        .add_rollback();
-    
-    // Player 2
-    commands
-        .spawn((
-            Player { handle: 1 },
-            BulletReady(true),
-            Speed(SHIP_SPEED),
-            Acceleration(Vec3::ZERO),
-            Transform::from_translation(Vec3::new(0.0, 20.0, 1_000.0)).looking_to(Vec3::Z, Vec3::Y),
-        ))
-        .add_rollback();
+/// Reliable channel the lobby is run over, next to the GGRS channel
+const LOBBY_CHANNEL: usize = 1;
+
+/// What peers tell each other over the lobby channel before the match starts
+#[derive(Serialize, Deserialize, Debug)]
+enum LobbyMessage {
+    /// The ship a player flies, and the flight model and
+    /// [`ShipDefs::digest`] it expects everybody to share, sent once
+    /// everybody has joined
+    Ship {
+        name: String,
+        flight_assist: FlightAssist,
+        ship_digest: u64,
+    },
+    /// Sent by spectators to every peer they meet, so players don't wait for them
+    Spectate,
+    /// Sent to spectators by the peer hosting them, with everything they
+    /// need to follow the match
+    Start {
+        num_players: usize,
+        ships: Vec<String>,
+        asteroid_seed: u64,
+        flight_assist: FlightAssist,
+        ship_digest: u64,
+    },
+    /// The host's answer to spectators showing up once it's too late to
+    /// add them to the session
+    Started,
+}
 
+impl LobbyMessage {
+    fn to_packet(&self) -> Box<[u8]> {
+        ron::to_string(self).expect("lobby messages serialize").into_bytes().into_boxed_slice()
+    }
+
+    fn from_packet(packet: &[u8]) -> Option<Self> {
+        ron::de::from_bytes(packet).ok()
+    }
+}
+
+/// What we know of the other peers while waiting for players
+#[derive(Resource, Default)]
+struct Lobby {
+    /// Our own pick is sent once everybody has joined, and can't be changed after
+    picked: bool,
+    /// Peers our pick has been sent to. Peers connecting after everybody
+    /// first seemed to have joined still need it.
+    sent_to: HashSet<PeerId>,
+    picks: HashMap<PeerId, String>,
+    spectators: HashSet<PeerId>,
+    /// Peers a spectator has already told it's spectating
+    announced: HashSet<PeerId>,
+    /// Why the match can't start, shown until it's sorted out
+    problem: Option<String>,
+    /// Whether we host the match's spectators, once it has started
+    hosting: bool,
 }
 
 fn start_matchbox_socket(mut commands: Commands, game_config: Res<GameConfig>) {
-    //let room_url = "ws://127.0.0.1:3536/extreme_bevy?next=2";
     info!("config {:?}", game_config);
     let room_url = game_config.room_url.clone();
     info!("connecting to matchbox server: {room_url}");
-    commands.insert_resource(MatchboxSocket::new_ggrs(room_url));
+    let socket = WebRtcSocketBuilder::new(room_url)
+        .add_ggrs_channel()
+        .add_reliable_channel();
+    commands.insert_resource(MatchboxSocket::from(socket));
+    commands.init_resource::<Lobby>();
+}
+
+/// Lets the local pilot pick a ship until the pick has been sent to the
+/// other peers, swapping the ship flown while waiting as they do
+fn pick_ship(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut game_config: ResMut<GameConfig>,
+    ship_defs: Res<ShipDefs>,
+    lobby: Res<Lobby>,
+    players: Query<(Entity, &Transform, &Player, &ShipClass)>,
+) {
+    if lobby.picked {
+        return;
+    }
+    let current = ship_defs.pick(game_config.ship.as_deref());
+    let mut picked = current;
+    egui::Area::new("ship_picker")
+        .anchor(Align2::LEFT_TOP, (10., 40.))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(RichText::new("Ship").color(Color32::WHITE));
+            for (class, def) in ship_defs.0.iter().enumerate() {
+                if ui.selectable_label(class == picked, &def.name).clicked() {
+                    picked = class;
+                }
+            }
+        });
+    if picked == current {
+        return;
+    }
+    game_config.ship = Some(ship_defs.get(picked).name.clone());
+    for (entity, transform, player, class) in &players {
+        if class.0 != picked {
+            commands.entity(entity).insert(new_ship(player.handle, *transform, picked, ship_defs.get(picked)));
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn wait_for_players(
     mut commands: Commands,
-    mut socket: ResMut<MatchboxSocket<SingleChannel>>,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    mut lobby: ResMut<Lobby>,
     mut next_state: ResMut<NextState<GameState>>,
     args: Res<Args>,
+    game_config: Res<GameConfig>,
+    ship_defs: Res<ShipDefs>,
+    arena: Res<Arena>,
 ) {
     if socket.get_channel(0).is_err() {
         return; // we've already started
@@ -767,26 +1029,147 @@ fn wait_for_players(
 
     // Check for new connections
     socket.update_peers();
-    let players = socket.players();
-
-    let num_players = 2;
-    if players.len() < num_players {
+    for (peer, packet) in socket.get_channel(LOBBY_CHANNEL).expect("lobby channel").receive() {
+        match LobbyMessage::from_packet(&packet) {
+            Some(LobbyMessage::Ship { name, flight_assist, ship_digest }) => {
+                // a peer flying with a different model or different ships
+                // would desync the match on its first turn, so it never gets
+                // to join it
+                let problem = if flight_assist != game_config.flight_assist {
+                    Some(format!(
+                        "a peer flies with {flight_assist:?} flight assist and we fly with {:?}, \
+                         everybody has to use the same",
+                        game_config.flight_assist
+                    ))
+                } else if ship_digest != ship_defs.digest() {
+                    Some("a peer has different ship definitions, everybody has to run the same build".to_string())
+                } else if ship_defs.class_of(&name).is_none() {
+                    Some(format!("a peer picked the unknown ship {name}"))
+                } else {
+                    None
+                };
+                if let Some(problem) = problem {
+                    error!("{problem}");
+                    lobby.problem = Some(problem);
+                    continue;
+                }
+                lobby.picks.insert(peer, name);
+            }
+            Some(LobbyMessage::Spectate) => {
+                lobby.spectators.insert(peer);
+            }
+            message => warn!("unexpected lobby message from {peer:?}: {message:?}"),
+        }
+    }
+    // spectators don't take up a slot
+    let players: Vec<_> = socket
+        .players()
+        .into_iter()
+        .filter(|player| !matches!(player, PlayerType::Remote(peer_id) if lobby.spectators.contains(peer_id)))
+        .collect();
+
+    // bots fill whatever slots people don't
+    let num_players = game_config.num_players;
+    if players.len() < num_players - game_config.bots.min(num_players - 1) {
         return; // wait for more players
     }
 
+    // swap ship picks over the lobby channel, GGRS only gets the socket
+    // once every pick is in. Peers can still connect after this, and wait
+    // for our pick like we wait for theirs, so every one of them gets it.
+    let our_ship = ship_defs.name_of(game_config.ship.as_deref());
+    lobby.picked = true;
+    let peers: Vec<PeerId> = socket
+        .connected_peers()
+        .filter(|peer| !lobby.sent_to.contains(peer) && !lobby.spectators.contains(peer))
+        .collect();
+    let channel = socket.get_channel(LOBBY_CHANNEL).expect("lobby channel");
+    for peer in peers {
+        let ship = LobbyMessage::Ship {
+            name: our_ship.clone(),
+            flight_assist: game_config.flight_assist,
+            ship_digest: ship_defs.digest(),
+        };
+        channel.send(ship.to_packet(), peer);
+        lobby.sent_to.insert(peer);
+    }
+    let mut ships = Vec::with_capacity(players.len());
+    for player in &players {
+        match player {
+            PlayerType::Remote(peer_id) => match lobby.picks.get(peer_id) {
+                Some(ship) => ships.push(ship.clone()),
+                None => return, // wait for its pick
+            },
+            _ => ships.push(our_ship.clone()),
+        }
+    }
+
     info!("All peers have joined, going in-game");
 
+    // every peer sees the same set of ids, so they all arrive at the same seed
+    let peer_ids = players
+        .iter()
+        .filter_map(|player| match player {
+            PlayerType::Remote(peer_id) => Some(*peer_id),
+            _ => None,
+        })
+        .chain(socket.id());
+    let asteroid_seed = field_seed(peer_ids);
+
+    // bots take the slots after the people's, and the peer with the lowest id
+    // flies all of them and hosts the spectators, so that every bot input
+    // comes from exactly one peer
+    let bot_handles = players.len()..num_players;
+    let host = players
+        .iter()
+        .filter_map(|player| match player {
+            PlayerType::Remote(peer_id) => Some(*peer_id),
+            _ => None,
+        })
+        .chain(socket.id())
+        .min_by_key(|peer_id| peer_id.0.as_u128());
+    let we_host = host.is_none() || host == socket.id();
+    let host = if we_host { None } else { host };
+    ships.extend(bot_handles.clone().map(|_| ship_defs.name_of(None)));
+
     // create a GGRS P2P session
     let mut session_builder = ggrs::SessionBuilder::<Config>::new()
         .with_num_players(num_players)
         .with_desync_detection_mode(DesyncDetection::On { interval: 1 })
-        .with_input_delay(args.input_delay);
+        .with_input_delay(game_config.input_delay);
 
     for (i, player) in players.into_iter().enumerate() {
         session_builder = session_builder
             .add_player(player, i)
             .expect("failed to add player");
     }
+    for handle in bot_handles.clone() {
+        session_builder = session_builder
+            .add_player(host.map_or(PlayerType::Local, PlayerType::Remote), handle)
+            .expect("failed to add bot");
+    }
+
+    // spectators get handles after every player's, and everything needed to
+    // follow along before GGRS takes over
+    lobby.hosting = we_host;
+    if we_host {
+        let mut spectators: Vec<PeerId> = lobby.spectators.iter().copied().collect();
+        spectators.sort_by_key(|peer_id| peer_id.0.as_u128());
+        let start = LobbyMessage::Start {
+            num_players,
+            ships: ships.clone(),
+            asteroid_seed,
+            flight_assist: game_config.flight_assist,
+            ship_digest: ship_defs.digest(),
+        };
+        let channel = socket.get_channel(LOBBY_CHANNEL).expect("lobby channel");
+        for (i, spectator) in spectators.into_iter().enumerate() {
+            session_builder = session_builder
+                .add_player(PlayerType::Spectator(spectator), num_players + i)
+                .expect("failed to add spectator");
+            channel.send(start.to_packet(), spectator);
+        }
+    }
 
     // move the channel out of the socket (required because GGRS takes ownership of it)
     let socket = socket.take_channel(0).unwrap();
@@ -796,300 +1179,219 @@ fn wait_for_players(
         .start_p2p_session(socket)
         .expect("failed to start session");
 
+    let choices = ship_defs.choices(&ships).expect("picks were checked on arrival");
+    reset_simulation(&mut commands, num_players, asteroid_seed, choices);
     commands.insert_resource(bevy_ggrs::Session::P2P(ggrs_session));
-    next_state.set(GameState::InGame);
-}
-
-fn handle_ggrs_events(mut session: ResMut<Session<Config>>) {
-    match session.as_mut() {
-        Session::P2P(s) => {
-            for event in s.events() {
-                match event {
-                    GgrsEvent::Disconnected { .. } | GgrsEvent::NetworkInterrupted { .. } => {
-                        warn!("GGRS event: {event:?}")
-                    }
-                    GgrsEvent::DesyncDetected {
-                        local_checksum,
-                        remote_checksum,
-                        frame,
-                        ..
-                    } => {
-                        error!("Desync on frame {frame}. Local checksum: {local_checksum:X}, remote checksum: {remote_checksum:X}");
-                    }
-                    _ => info!("GGRS event: {event:?}"),
-                }
-            }
-        }
-        _ => {}
+    start_recording(&mut commands, &args, num_players, game_config.input_delay, asteroid_seed, game_config.flight_assist, *arena, &ships);
+    if we_host && !bot_handles.is_empty() {
+        commands.insert_resource(Bots::new(bot_handles, game_config.bot_difficulty));
     }
+    next_state.set(GameState::InGame);
 }
 
-fn move_players(
-    local_players: Option<Res<LocalPlayers>>,
-    mut players: Query<(&mut Transform, &mut Speed, &mut Acceleration, &Player), Without<FollowPlayer>>,
-    mut follow_players: Query<(&mut Transform, &mut Visibility, &FollowPlayer), With<FollowPlayer>>,
-    local_inputs: Option<Res<LocalInputs<Config>>>,
-    inputs: Option<Res<PlayerInputs<Config>>>,
-    time: Res<Time>,
+/// Tells every peer we meet that we're only watching, then follows the match
+/// through a spectator session once its host says it has started
+#[allow(clippy::too_many_arguments)]
+fn wait_for_match(
+    mut commands: Commands,
+    mut socket: ResMut<MatchboxSocket<MultipleChannels>>,
+    mut lobby: ResMut<Lobby>,
+    mut next_state: ResMut<NextState<GameState>>,
+    args: Res<Args>,
+    mut game_config: ResMut<GameConfig>,
+    ship_defs: Res<ShipDefs>,
+    arena: Res<Arena>,
 ) {
-    let mut observer_pos = Vec3::ZERO;
-    if let Some(local_players) = &local_players {
-        for (player_transform, _, _, player) in &players {
-            if local_players.0.contains(&player.handle) {
-                observer_pos = player_transform.translation;
-                break;
-            }
-        }
+    if socket.get_channel(0).is_err() {
+        return; // we've already started
     }
-    // move players
-    for (mut transform, speed , _acceleration, player) in &mut players {
-        let input: [u8; 3];
-        if let Some(inputs) = &inputs {
-            input = inputs[player.handle].0;
-        } else if let Some(inputs) = &local_inputs {
-            input = inputs.0[&player.handle];
-        } else {
-            input = [0; 3];
-        }
-        let angular_thrust_pitch = angular_thrust_pitch(input);
-        if angular_thrust_pitch != 0.0 {
-            transform.rotate_local_axis(Vec3::X, angular_thrust_pitch * std::f32::consts::PI / 180.0 * time.delta_seconds());
-        }
-        let angular_thrust_roll = angular_thrust_roll(input);
-        if angular_thrust_roll != 0.0 {
-            transform.rotate_local_axis(Vec3::Z, angular_thrust_roll * std::f32::consts::PI / 180.0 * time.delta_seconds());
-        }
-        let velocity = transform.rotation.mul_vec3(Vec3::Z) * speed.0;
-        transform.translation += velocity * time.delta_seconds();
-        transform.translation = crate::math::warp_infinite_space_into_finite_cube(transform.translation);
+
+    socket.update_peers();
+    let peers: Vec<PeerId> = socket.connected_peers().filter(|peer| !lobby.announced.contains(peer)).collect();
+    let channel = socket.get_channel(LOBBY_CHANNEL).expect("lobby channel");
+    for peer in peers {
+        channel.send(LobbyMessage::Spectate.to_packet(), peer);
+        lobby.announced.insert(peer);
     }
-    // update model positions/visibility
-    for (mut transform, mut visibility, follow_player) in &mut follow_players {
-        let mut player_found: bool = false;
-        for (player_transform, _, _, player) in &players {
-            if player.handle != follow_player.target_player_handle {
-                continue;
+
+    let mut start = None;
+    for (peer, packet) in channel.receive() {
+        // ship picks and other spectators are none of our business
+        match LobbyMessage::from_packet(&packet) {
+            Some(LobbyMessage::Start { num_players, ships, asteroid_seed, flight_assist, ship_digest }) => {
+                start = Some((peer, num_players, ships, asteroid_seed, flight_assist, ship_digest));
             }
-            *transform = *player_transform;
-            if let Some(local_players) = &local_players {
-                if !local_players.0.is_empty() && !local_players.0.contains(&player.handle) {
-                    transform.translation = crate::math::finite_cube_point_to_closest_visible_location(observer_pos, transform.translation);
-                }
+            Some(LobbyMessage::Started) if lobby.problem.is_none() => {
+                let problem = "the match has already started, spectators have to join before it does".to_string();
+                error!("{problem}");
+                lobby.problem = Some(problem);
             }
-            player_found = true;
-            break;
+            _ => {}
         }
-        *visibility = if player_found { Visibility::Visible } else { Visibility::Hidden };
     }
-}
+    let Some((host, num_players, ships, asteroid_seed, flight_assist, ship_digest)) = start else {
+        return; // wait for the match to start
+    };
 
-fn reload_bullet(
-    inputs: Option<Res<PlayerInputs<Config>>>,
-    local_inputs: Option<Res<LocalInputs<Config>>>,
-    mut players: Query<(&mut BulletReady, &Player)>,
-) {
-    for (mut can_fire, player) in players.iter_mut() {
-        let input: [u8; 3];
-        if let Some(inputs) = &inputs {
-            input = inputs[player.handle].0;
-        } else if let Some(inputs) = &local_inputs {
-            input = inputs.0[&player.handle];
-        } else {
-            input = [0; 3];
-        }
-        if !fire(input) {
-            can_fire.0 = true;
+    // other ship definitions would desync on the first turn
+    let choices = if ship_digest == ship_defs.digest() {
+        ship_defs.choices(&ships)
+    } else {
+        Err("the match has different ship definitions, spectators have to run the same build".to_string())
+    };
+    let choices = match choices {
+        Ok(choices) => choices,
+        Err(problem) => {
+            error!("can't follow the match: {problem}");
+            lobby.problem = Some(problem);
+            return;
         }
-    }
-}
+    };
 
-fn fire_bullets(
-    mut commands: Commands,
-    inputs: Option<Res<PlayerInputs<Config>>>,
-    local_inputs: Option<Res<LocalInputs<Config>>>,
-    mut players: Query<(&Transform, &Player, &mut BulletReady)>,
-    time: Res<Time>,
-) {
-    for (transform, player, mut bullet_ready) in &mut players {
-        let input: [u8; 3];
-        if let Some(inputs) = &inputs {
-            input = inputs[player.handle].0;
-        } else if let Some(inputs) = &local_inputs {
-            input = inputs.0[&player.handle];
-        } else {
-            input = [0; 3];
-        }
-        if fire(input) && bullet_ready.0 {
-            let bullet_transform = *transform * Transform::from_translation(Vec3::new(0.0, 0.0, 2.0));
-            let offset_width: f32 = 2.2;
-            let offset_height: f32 = 1.0;
-            let bullet_offsets: [[f32; 2]; 4] = [
-                [-offset_width, -offset_height],
-                [-offset_width, offset_height],
-                [offset_width, -offset_height],
-                [offset_width, offset_height],
-            ];
-            for bullet_offset in bullet_offsets {
-                let transform = bullet_transform * Transform::from_translation(Vec3::new(bullet_offset[0], bullet_offset[1], 0.0));
-                commands
-                    .spawn((
-                        Bullet,
-                        BirthTime(time.elapsed_seconds()),
-                        transform,
-                    ))
-                    .add_rollback();
-            }
-            bullet_ready.0 = false;
-        }
-    }
+    info!("The match has started, spectating through {host:?}");
+
+    let ggrs_session = ggrs::SessionBuilder::<Config>::new()
+        .with_num_players(num_players)
+        .start_spectator_session(host, socket.take_channel(0).unwrap());
+
+    // spawning goes by the player count and flying by the flight model,
+    // which are the match's and not ours
+    game_config.num_players = num_players;
+    game_config.flight_assist = flight_assist;
+    commands.insert_resource(flight_assist);
+    reset_simulation(&mut commands, num_players, asteroid_seed, choices);
+    commands.insert_resource(bevy_ggrs::Session::Spectator(ggrs_session));
+    commands.init_resource::<SpectatorCamera>();
+    start_recording(&mut commands, &args, num_players, game_config.input_delay, asteroid_seed, flight_assist, *arena, &ships);
+    next_state.set(GameState::InGame);
 }
 
-fn move_bullet(
-    mut commands: Commands,
-    local_players: Option<Res<LocalPlayers>>,
-    players: Query<(&mut Transform, &Player), Without<Bullet>>,
-    mut bullets: Query<(Entity, &mut Transform, &BirthTime), With<Bullet>>,
-    mut bullet_meshes: Query<(Entity, &mut Transform, &FollowBullet), (Without<Bullet>, Without<Player>)>,
-    models: Res<ModelAssets2>,
-    time: Res<Time>
-) {
-    let mut observer_pos = Vec3::ZERO;
-    for (player_transform, player) in &players {
-        if let Some(local_players) = &local_players {
-            if local_players.0.is_empty() || local_players.0.contains(&player.handle) {
-                observer_pos = player_transform.translation;
-                break;
-            }
-        } else {
-            observer_pos = player_transform.translation;
-        }
-    }
-    const BULLET_DIE_IN_SECONDS: f32 = 10.0;
-    let mut bullet_transforms: Vec<(Transform,bool)> = Vec::new();
-    for (bullet_entity, mut transform, birth_time) in &mut bullets {
-        let bullet_age = time.elapsed_seconds() - birth_time.0;
-        if bullet_age >= BULLET_DIE_IN_SECONDS {
-            commands.entity(bullet_entity).despawn_recursive();
-        } else {
-            let speed = 200.;
-            let delta = transform.rotation * (Vec3::Z * speed * time.delta_seconds());
-            transform.translation += delta;
-            transform.translation = crate::math::warp_infinite_space_into_finite_cube(transform.translation);
-            bullet_transforms.push((*transform, false));
-        }
+/// Lets spectators that turn up once the match is underway know they've
+/// missed it, rather than have them wait for a start that never comes
+fn turn_away_late_spectators(mut socket: ResMut<MatchboxSocket<MultipleChannels>>, lobby: Res<Lobby>) {
+    if !lobby.hosting {
+        return;
     }
-    for (bullet_mesh_entity, mut bullet_mesh_transform, follow_bullet) in &mut bullet_meshes {
-        if follow_bullet.index >= bullet_transforms.len() {
-            commands.entity(bullet_mesh_entity).despawn_recursive();
-            continue;
-        }
-        *bullet_mesh_transform = bullet_transforms[follow_bullet.index].0;
-        bullet_mesh_transform.translation = crate::math::finite_cube_point_to_closest_visible_location(observer_pos, bullet_mesh_transform.translation);
-        bullet_transforms[follow_bullet.index].1 = true;
-    }
-    let mut index: usize = 0;
-    for bullet_transform in bullet_transforms {
-        if !bullet_transform.1 {
-            commands.spawn((
-                FollowBullet { index, },
-                MaterialMeshBundle::<CustomStandardMaterial> {
-                    transform: bullet_transform.0,
-                    mesh: models.bullet_mesh.clone(),
-                    material: models.bullet_material.clone(),
-                    ..default()
-                },
-            ));
+    socket.update_peers();
+    let channel = socket.get_channel(LOBBY_CHANNEL).expect("lobby channel");
+    for (peer, packet) in channel.receive() {
+        // late ship picks don't matter any more either
+        if let Some(LobbyMessage::Spectate) = LobbyMessage::from_packet(&packet) {
+            info!("turning away {peer:?}, who wants to spectate a match already started");
+            channel.send(LobbyMessage::Started.to_packet(), peer);
         }
-        index += 1;
     }
 }
 
-const PLAYER_RADIUS: f32 = 3.0;
-const BULLET_RADIUS: f32 = 0.5;
-
-fn kill_players(
-    mut commands: Commands,
-    players: Query<(Entity, &Transform, &Player), Without<Bullet>>,
-    bullets: Query<&Transform, With<Bullet>>,
-    mut next_state: ResMut<NextState<RollbackState>>,
-    mut scores: ResMut<Scores>,
-) {
-    for (player_entity, player_transform, player) in &players {
-        for bullet_transform in &bullets {
-            let bullet_pos = crate::math::finite_cube_point_to_closest_visible_location(
-                player_transform.translation,
-                crate::math::warp_infinite_space_into_finite_cube(bullet_transform.translation)
-            );
-            let distance = Vec3::distance(
-                player_transform.translation,
-                bullet_pos,
-            );
-            if distance < PLAYER_RADIUS + BULLET_RADIUS {
-                commands.entity(player_entity).despawn_recursive();
-                next_state.set(RollbackState::RoundEnd);
-
-                if player.handle == 0 {
-                    scores.1 += 1;
-                } else {
-                    scores.0 += 1;
-                }
-                info!("player died: {scores:?}");
-                break;
-            }
-        }
-    }
+/// Tells the local pilot why the match isn't starting
+fn show_lobby_problem(mut contexts: EguiContexts, lobby: Res<Lobby>) {
+    let Some(problem) = &lobby.problem else { return; };
+    egui::Area::new("lobby_problem")
+        .anchor(Align2::CENTER_TOP, (0., 40.))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(RichText::new(problem).color(Color32::RED));
+        });
 }
 
-fn camera_follow(
+fn handle_ggrs_events(
+    mut session: ResMut<Session<Config>>,
+    dumps: Res<FrameDumps>,
     local_players: Option<Res<LocalPlayers>>,
-    players: Query<(&Player, &Transform)>,
-    mut cameras: Query<&mut Transform, (With<Camera>, Without<Player>)>,
-    time: Res<Time>,
 ) {
-    for (player, player_transform) in &players {
-        if let Some(local_players) = &local_players {
-            if !local_players.0.is_empty() {
-                if !local_players.0.contains(&player.handle) {
-                    continue;
+    match session.as_mut() {
+        Session::P2P(s) => {
+            for event in s.events() {
+                match event {
+                    GgrsEvent::Disconnected { .. } | GgrsEvent::NetworkInterrupted { .. } => {
+                        warn!("GGRS event: {event:?}")
+                    }
+                    GgrsEvent::DesyncDetected {
+                        local_checksum,
+                        remote_checksum,
+                        frame,
+                        ..
+                    } => {
+                        error!("Desync on frame {frame}. Local checksum: {local_checksum:X}, remote checksum: {remote_checksum:X}");
+                        let local_handles = local_players.as_ref().map(|p| p.0.as_slice()).unwrap_or(&[]);
+                        write_desync_dump(&dumps, frame, local_handles);
+                    }
+                    _ => info!("GGRS event: {event:?}"),
                 }
             }
         }
-        for mut transform in &mut cameras {
-            transform.translation = crate::math::finite_cube_point_to_closest_visible_location(
-                player_transform.translation,
-                crate::math::warp_infinite_space_into_finite_cube(transform.translation)
-            );
-            let target = player_transform.transform_point(Vec3::new(0.0, 1.5, -10.0));
-            let delta = (target - transform.translation) * (10.0f32 * time.delta_seconds()).min(1.0);
-            transform.translation += delta;
-            let target_rotation = player_transform.rotation * Quat::from_rotation_y(std::f32::consts::PI);
-            transform.rotation = transform.rotation.lerp(target_rotation, (6.0f32 * time.delta_seconds()).min(1.0));
+        Session::Spectator(s) => {
+            for event in s.events() {
+                match event {
+                    GgrsEvent::Disconnected { .. } | GgrsEvent::NetworkInterrupted { .. } => {
+                        warn!("GGRS event: {event:?}")
+                    }
+                    _ => info!("GGRS event: {event:?}"),
+                }
+            }
         }
-    }
-}
-
-fn round_end_timeout(
-    mut timer: ResMut<RoundEndTimer>,
-    mut state: ResMut<NextState<RollbackState>>,
-    time: Res<Time>,
-) {
-    timer.tick(time.delta());
-
-    if timer.just_finished() {
-        state.set(RollbackState::InRound);
+        _ => {}
     }
 }
 
 fn update_score_ui(mut contexts: EguiContexts, scores: Res<Scores>) {
-    let Scores(p1_score, p2_score) = *scores;
+    let score_text = scores.0
+        .iter()
+        .map(|score| score.to_string())
+        .collect::<Vec<_>>()
+        .join(" - ");
 
     egui::Area::new("score")
         .anchor(Align2::CENTER_TOP, (0., 25.))
         .show(contexts.ctx_mut(), |ui| {
             ui.label(
-                RichText::new(format!("{p1_score} - {p2_score}"))
+                RichText::new(score_text)
                     .color(Color32::RED)
                     .font(FontId::proportional(72.0)),
             );
         });
 }
+
+/// Hull, shield and cannon heat bars of the local pilot, and what's left of
+/// its missiles and flares
+fn update_health_hud(
+    mut contexts: EguiContexts,
+    players: Query<(&Player, &ShipClass, &Health, &Shield, &Weapon, &MissileAmmo, &FlareAmmo)>,
+    viewpoint: Viewpoint,
+    ship_defs: Res<ShipDefs>,
+) {
+    let watched = viewpoint.handles();
+    // each split-screen pilot's bars go under its own half
+    let screen_width = contexts.ctx_mut().screen_rect().width() / watched.len().max(1) as f32;
+    for (pilot, local_handle) in watched.iter().enumerate() {
+        let Some((_, class, health, shield, weapon, missile_ammo, flare_ammo)) = players.iter().find(|(player, ..)| player.handle == *local_handle) else {
+            continue;
+        };
+        let def = ship_defs.get(class.0);
+        let offset = (pilot as f32 + 0.5 - watched.len() as f32 / 2.0) * screen_width;
+
+        egui::Area::new(format!("health{pilot}"))
+            .anchor(Align2::CENTER_BOTTOM, (offset, -25.))
+            .show(contexts.ctx_mut(), |ui| {
+                ui.set_width(200.0);
+                ui.add(
+                    egui::ProgressBar::new(shield.charge / def.max_shield)
+                        .fill(Color32::from_rgb(60, 140, 255))
+                        .text(format!("Shield {:.0}", shield.charge)),
+                );
+                ui.add(
+                    egui::ProgressBar::new(health.0.max(0.0) / def.max_health)
+                        .fill(Color32::from_rgb(220, 60, 60))
+                        .text(format!("Hull {:.0}", health.0.max(0.0))),
+                );
+                ui.add(
+                    egui::ProgressBar::new(weapon.heat / def.cannon.max_heat)
+                        .fill(if weapon.overheated { Color32::from_rgb(255, 80, 0) } else { Color32::from_rgb(230, 180, 40) })
+                        .text(if weapon.overheated { "Overheated".to_string() } else { format!("Heat {:.0}", weapon.heat) }),
+                );
+                ui.label(
+                    egui::RichText::new(format!("Missiles {}   Flares {}", missile_ammo.0, flare_ammo.0))
+                        .color(Color32::WHITE),
+                );
+            });
+    }
+}
@@ -0,0 +1,625 @@
+use crate::arena::*;
+use crate::asteroids::*;
+use crate::components::*;
+use crate::flight::*;
+use crate::config::GameConfig;
+use crate::input::*;
+use crate::missiles::*;
+use crate::ships::*;
+use crate::spatial_grid::SpatialGrid;
+use bevy::{prelude::*, utils::FixedState};
+use bevy_ggrs::{prelude::*, *};
+use bevy_matchbox::prelude::PeerId;
+use bevy_roll_safe::prelude::*;
+use serde::Deserialize;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+/// Bullets expire after 10 seconds at the 60 fps GGRS update rate
+const BULLET_LIFETIME_FRAMES: u32 = 10 * 60;
+pub const BULLET_SPEED: f32 = 200.0;
+/// How a gun fires and heats up, tuned separately for each ship's weapon
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct WeaponStats {
+    /// Damage of each bullet of a volley
+    pub damage: f32,
+    /// Frames between volleys while the trigger is held
+    pub shot_interval_frames: u32,
+    pub heat_per_shot: f32,
+    /// Heat lost per second
+    pub cooling_rate: f32,
+    /// The gun overheats on reaching this much heat...
+    pub max_heat: f32,
+    /// ...and can't fire again until it has cooled down to this
+    pub recover_heat: f32,
+}
+
+/// Shields start recharging 3 seconds after the last hit
+const SHIELD_RECHARGE_DELAY_FRAMES: u32 = 3 * 60;
+/// Shield points regained per second while recharging
+const SHIELD_RECHARGE_RATE: f32 = 10.0;
+
+// The first generic parameter, u8, is the input type: 4-directions + fire fits
+// easily in a single byte
+// The second parameter is the address type of peers: Matchbox' WebRtcSocket
+// addresses are called `PeerId`s
+pub type Config = bevy_ggrs::GgrsConfig<[u8; 3], PeerId>;
+
+/// Everything that takes part in the rollback simulation: the rollback state,
+/// resources and components, and the systems that run in [`GgrsSchedule`].
+///
+/// Contains no rendering, windowing or UI, so it can run under
+/// [`MinimalPlugins`] for headless matches.
+pub struct GameplayPlugin;
+
+impl Plugin for GameplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins(GgrsPlugin::<Config>::default())
+            .add_ggrs_state::<RollbackState>()
+            .rollback_resource_with_clone::<RoundEndTimer>()
+            .rollback_resource_with_clone::<Scores>()
+            .rollback_component_with_clone::<Transform>()
+            .rollback_component_with_copy::<Weapon>()
+            .rollback_component_with_copy::<BirthFrame>()
+            .rollback_component_with_copy::<Bullet>()
+            .rollback_component_with_copy::<BulletId>()
+            .rollback_component_with_copy::<BulletOwner>()
+            .rollback_component_with_copy::<Player>()
+            .rollback_component_with_copy::<ShipClass>()
+            .rollback_component_with_copy::<Speed>()
+            .rollback_component_with_copy::<Velocity>()
+            .rollback_component_with_copy::<Acceleration>()
+            .rollback_component_with_copy::<Throttle>()
+            .rollback_component_with_copy::<BoostFuel>()
+            .rollback_component_with_copy::<Health>()
+            .rollback_component_with_copy::<Shield>()
+            .rollback_component_with_copy::<Damage>()
+            .rollback_component_with_copy::<Asteroid>()
+            .rollback_component_with_copy::<AsteroidId>()
+            .rollback_component_with_copy::<Missile>()
+            .rollback_component_with_copy::<MissileAmmo>()
+            .rollback_component_with_copy::<MissileReady>()
+            .rollback_component_with_copy::<Flare>()
+            .rollback_component_with_copy::<FlareAmmo>()
+            .rollback_component_with_copy::<FlareCooldown>()
+            .checksum_component::<Transform>(checksum_transform)
+            .checksum_component::<Player>(checksum_player)
+            .checksum_component::<ShipClass>(checksum_ship_class)
+            .checksum_component::<Weapon>(checksum_weapon)
+            .checksum_component::<BulletId>(checksum_bullet_id)
+            .checksum_component::<BulletOwner>(checksum_bullet_owner)
+            .checksum_component::<Speed>(checksum_speed)
+            .checksum_component::<BirthFrame>(checksum_birth_frame)
+            .checksum_component::<Velocity>(checksum_velocity)
+            .checksum_component::<Acceleration>(checksum_acceleration)
+            .checksum_component::<Throttle>(checksum_throttle)
+            .checksum_component::<BoostFuel>(checksum_boost_fuel)
+            .checksum_component::<Health>(checksum_health)
+            .checksum_component::<Shield>(checksum_shield)
+            .checksum_component::<Damage>(checksum_damage)
+            .checksum_component::<Asteroid>(checksum_asteroid)
+            .checksum_component::<AsteroidId>(checksum_asteroid_id)
+            .checksum_component::<Missile>(checksum_missile)
+            .checksum_component::<MissileAmmo>(checksum_missile_ammo)
+            .checksum_component::<MissileReady>(checksum_missile_ready)
+            .checksum_component::<FlareAmmo>(checksum_flare_ammo)
+            .checksum_component::<FlareCooldown>(checksum_flare_cooldown)
+            .checksum_resource::<Scores>(checksum_scores)
+            .checksum_resource::<RoundEndTimer>(checksum_round_end_timer)
+            .checksum_resource::<SimulationFrame>(checksum_simulation_frame)
+            .checksum_resource::<NextBulletId>(checksum_next_bullet_id)
+            .checksum_resource::<Arena>(checksum_arena)
+            .checksum_resource::<AsteroidSeed>(checksum_asteroid_seed)
+            .checksum_resource::<NextAsteroidId>(checksum_next_asteroid_id)
+            .checksum_resource::<ShipChoices>(checksum_ship_choices)
+            .rollback_resource_with_copy::<SimulationFrame>()
+            .rollback_resource_with_copy::<NextBulletId>()
+            .rollback_resource_with_copy::<Arena>()
+            .rollback_resource_with_copy::<AsteroidSeed>()
+            .rollback_resource_with_copy::<NextAsteroidId>()
+            .rollback_resource_with_clone::<ShipChoices>()
+            .init_resource::<RoundEndTimer>()
+            .init_resource::<Scores>()
+            .init_resource::<SimulationFrame>()
+            .init_resource::<NextBulletId>()
+            .init_resource::<FlightAssist>()
+            .init_resource::<Arena>()
+            .init_resource::<AsteroidSeed>()
+            .init_resource::<NextAsteroidId>()
+            .init_resource::<ShipChoices>()
+            .add_systems(OnEnter(RollbackState::InRound), (spawn_players, spawn_asteroids))
+            .add_systems(
+                GgrsSchedule,
+                (
+                    move_players,
+                    cool_weapons,
+                    fire_bullets.after(move_players).after(cool_weapons),
+                    move_bullet.after(fire_bullets),
+                    fire_missiles.after(move_players).after(fire_bullets),
+                    drop_flares.after(move_players).after(fire_missiles),
+                    move_flares.after(drop_flares),
+                    guide_missiles.after(fire_missiles).after(move_flares),
+                    recharge_shields,
+                    crash_into_asteroids.after(move_players).after(recharge_shields),
+                    shoot_asteroids.after(move_bullet).after(guide_missiles),
+                    kill_players
+                        .after(move_bullet)
+                        .after(guide_missiles)
+                        .after(move_players)
+                        .after(recharge_shields)
+                        .after(crash_into_asteroids)
+                        .after(shoot_asteroids),
+                )
+                    .run_if(in_state(RollbackState::InRound))
+                    .after(apply_state_transition::<RollbackState>),
+            )
+            .add_systems(
+                GgrsSchedule,
+                // after every system that reads the frame; kill_players
+                // already runs after the rest of the round's systems
+                tick_simulation_frame
+                    .after(apply_state_transition::<RollbackState>)
+                    .after(kill_players)
+                    .after(round_end_timeout),
+            )
+            .add_systems(
+                GgrsSchedule,
+                round_end_timeout
+                    .run_if(in_state(RollbackState::RoundEnd))
+                    .ambiguous_with(kill_players)
+                    .after(apply_state_transition::<RollbackState>),
+            );
+    }
+}
+
+#[derive(States, Clone, Eq, PartialEq, Debug, Hash, Default)]
+pub enum RollbackState {
+    /// When the characters running and gunning
+    #[default]
+    InRound,
+    /// When one character is dead, and we're transitioning to the next round
+    RoundEnd,
+}
+
+#[derive(Resource, Clone, Deref, DerefMut)]
+pub struct RoundEndTimer(Timer);
+
+/// Number of frames simulated since the session started
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct SimulationFrame(pub u32);
+
+/// Hands out the [`BulletId`]s that presentation uses to tell bullets apart
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct NextBulletId(u32);
+
+impl NextBulletId {
+    pub fn take(&mut self) -> BulletId {
+        let id = BulletId(self.0);
+        self.0 = self.0.wrapping_add(1);
+        id
+    }
+}
+
+pub fn checksum_next_bullet_id(next_bullet_id: &NextBulletId) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    next_bullet_id.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_scores(scores: &Scores) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    scores.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_round_end_timer(timer: &RoundEndTimer) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    timer.elapsed().hash(&mut hasher);
+    timer.times_finished_this_tick().hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn checksum_simulation_frame(frame: &SimulationFrame) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    frame.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Kill count per player handle
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Scores(pub Vec<u32>);
+
+impl Scores {
+    pub fn new(num_players: usize) -> Self {
+        Scores(vec![0; num_players])
+    }
+
+    pub fn add_point(&mut self, handle: usize) {
+        if handle >= self.0.len() {
+            self.0.resize(handle + 1, 0);
+        }
+        self.0[handle] += 1;
+    }
+}
+
+impl Default for RoundEndTimer {
+    fn default() -> Self {
+        RoundEndTimer(Timer::from_seconds(1.0, TimerMode::Repeating))
+    }
+}
+
+/// Resets the rollback resources to how every peer starts a session, dropping
+/// anything accumulated while flying around during matchmaking
+pub fn reset_simulation(commands: &mut Commands, num_players: usize, asteroid_seed: u64, ship_choices: ShipChoices) {
+    commands.insert_resource(Scores::new(num_players));
+    commands.insert_resource(SimulationFrame::default());
+    commands.insert_resource(NextBulletId::default());
+    commands.insert_resource(AsteroidSeed(asteroid_seed));
+    commands.insert_resource(NextAsteroidId::default());
+    commands.insert_resource(ship_choices);
+}
+
+/// Creates a session in which every player is local, rolling back and
+/// resimulating `check_distance` frames every frame to catch non-determinism,
+/// or never rolling back if it's 0
+pub fn new_synctest_session(num_players: usize, input_delay: usize, check_distance: usize) -> Session<Config> {
+    let mut session_builder = ggrs::SessionBuilder::<Config>::new()
+        .with_num_players(num_players)
+        .with_check_distance(check_distance)
+        .with_input_delay(input_delay);
+
+    for i in 0..num_players {
+        session_builder = session_builder
+            .add_player(PlayerType::Local, i)
+            .expect("failed to add player");
+    }
+
+    let ggrs_session = session_builder
+        .start_synctest_session()
+        .expect("failed to start session");
+
+    Session::SyncTest(ggrs_session)
+}
+
+/// Components of a ship of class `class` at full throttle, cruising along its nose
+pub fn new_ship(handle: usize, transform: Transform, class: usize, def: &ShipDef) -> impl Bundle {
+    (
+        Player { handle },
+        ShipClass(class),
+        Weapon::default(),
+        Speed(def.speed),
+        Velocity(transform.rotation.mul_vec3(Vec3::Z) * def.speed),
+        Acceleration(Vec3::ZERO),
+        Throttle(1.0),
+        BoostFuel(BOOST_FUEL_SECONDS),
+        Health(def.max_health),
+        Shield {
+            charge: def.max_shield,
+            last_hit_frame: 0,
+        },
+        (MissileAmmo(def.missiles), MissileReady(true), FlareAmmo(def.flares), FlareCooldown(0)),
+        transform,
+    )
+}
+
+/// Spawn points are spread evenly on a ring around the middle of the arena,
+/// each pilot facing the centre
+pub fn spawn_transform(handle: usize, num_players: usize, arena: Arena) -> Transform {
+    let radius = arena.size / 8.0;
+    let angle = std::f32::consts::TAU * (handle as f32) / (num_players.max(1) as f32);
+    let pos = Vec3::new(angle.sin(), 0.0, angle.cos()) * radius;
+    // ship noses point along +Z, so look away from the centre
+    let transform = Transform::from_translation(pos).looking_to(pos, Vec3::Y);
+    Transform {
+        translation: arena.wrap(transform.translation),
+        ..transform
+    }
+}
+
+fn spawn_players(
+    mut commands: Commands,
+    players: Query<Entity, With<Player>>,
+    projectiles: Query<Entity, Or<(With<Bullet>, With<Missile>, With<Flare>)>>,
+    game_config: Res<GameConfig>,
+    arena: Res<Arena>,
+    ship_defs: Res<ShipDefs>,
+    ship_choices: Res<ShipChoices>,
+) {
+    info!("Spawning players");
+
+    for player in &players {
+        commands.entity(player).despawn_recursive();
+    }
+
+    for projectile in &projectiles {
+        commands.entity(projectile).despawn_recursive();
+    }
+
+    for handle in 0..game_config.num_players {
+        let class = ship_choices.get(handle);
+        let transform = spawn_transform(handle, game_config.num_players, *arena);
+        commands
+            .spawn(new_ship(handle, transform, class, ship_defs.get(class)))
+            .add_rollback();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn move_players(
+    mut players: Query<(&mut Transform, &mut Velocity, &mut Acceleration, &mut Throttle, &mut BoostFuel, &Speed, &Player, &ShipClass)>,
+    local_inputs: Option<Res<LocalInputs<Config>>>,
+    inputs: Option<Res<PlayerInputs<Config>>>,
+    flight_assist: Res<FlightAssist>,
+    arena: Res<Arena>,
+    ship_defs: Res<ShipDefs>,
+    time: Res<Time>,
+) {
+    for (mut transform, mut velocity, mut acceleration, mut throttle, mut boost_fuel, speed, player, class) in &mut players {
+        let def = ship_defs.get(class.0);
+        let input: [u8; 3];
+        if let Some(inputs) = &inputs {
+            input = inputs[player.handle].0;
+        } else if let Some(inputs) = &local_inputs {
+            input = inputs.0[&player.handle];
+        } else {
+            input = [0; 3];
+        }
+        let angular_thrust_pitch = pitch_input(input) * def.pitch_speed;
+        if angular_thrust_pitch != 0.0 {
+            transform.rotate_local_axis(Vec3::X, angular_thrust_pitch * std::f32::consts::PI / 180.0 * time.delta_seconds());
+        }
+        let angular_thrust_roll = roll_input(input) * def.roll_speed;
+        if angular_thrust_roll != 0.0 {
+            transform.rotate_local_axis(Vec3::Z, angular_thrust_roll * std::f32::consts::PI / 180.0 * time.delta_seconds());
+        }
+        fly(
+            *flight_assist,
+            *arena,
+            input,
+            speed,
+            &mut transform,
+            &mut velocity,
+            &mut acceleration,
+            &mut throttle,
+            &mut boost_fuel,
+            time.delta_seconds(),
+        );
+    }
+}
+
+/// Counts down to the next volley and bleeds off heat
+pub fn cool_weapons(mut weapons: Query<(&mut Weapon, &ShipClass)>, ship_defs: Res<ShipDefs>, time: Res<Time>) {
+    for (mut weapon, class) in &mut weapons {
+        let cannon = ship_defs.get(class.0).cannon;
+        weapon.cooldown = weapon.cooldown.saturating_sub(1);
+        weapon.heat = (weapon.heat - cannon.cooling_rate * time.delta_seconds()).max(0.0);
+        if weapon.overheated && weapon.heat <= cannon.recover_heat {
+            weapon.overheated = false;
+        }
+    }
+}
+
+/// Fires a volley whenever the trigger is held and the cannons are ready,
+/// heating them up
+pub fn fire_bullets(
+    mut commands: Commands,
+    inputs: Option<Res<PlayerInputs<Config>>>,
+    local_inputs: Option<Res<LocalInputs<Config>>>,
+    mut players: Query<(&Transform, &Player, &ShipClass, &mut Weapon)>,
+    frame: Res<SimulationFrame>,
+    ship_defs: Res<ShipDefs>,
+    mut next_bullet_id: ResMut<NextBulletId>,
+) {
+    for (transform, player, class, mut weapon) in &mut players {
+        let def = ship_defs.get(class.0);
+        let input: [u8; 3];
+        if let Some(inputs) = &inputs {
+            input = inputs[player.handle].0;
+        } else if let Some(inputs) = &local_inputs {
+            input = inputs.0[&player.handle];
+        } else {
+            input = [0; 3];
+        }
+        if fire(input) && weapon.cooldown == 0 && !weapon.overheated {
+            for hardpoint in &def.hardpoints {
+                let transform = *transform * Transform::from_translation(*hardpoint);
+                commands
+                    .spawn((
+                        Bullet,
+                        next_bullet_id.take(),
+                        BulletOwner { handle: player.handle },
+                        BirthFrame(frame.0),
+                        Damage(def.cannon.damage),
+                        transform,
+                    ))
+                    .add_rollback();
+            }
+            weapon.cooldown = def.cannon.shot_interval_frames;
+            weapon.heat += def.cannon.heat_per_shot;
+            if weapon.heat >= def.cannon.max_heat {
+                weapon.overheated = true;
+            }
+        }
+    }
+}
+
+pub fn move_bullet(
+    mut commands: Commands,
+    mut bullets: Query<(Entity, &mut Transform, &BirthFrame), With<Bullet>>,
+    frame: Res<SimulationFrame>,
+    arena: Res<Arena>,
+    time: Res<Time>
+) {
+    for (bullet_entity, mut transform, birth_frame) in &mut bullets {
+        let bullet_age = frame.0.wrapping_sub(birth_frame.0);
+        if bullet_age >= BULLET_LIFETIME_FRAMES {
+            commands.entity(bullet_entity).despawn_recursive();
+        } else {
+            let delta = transform.rotation * (Vec3::Z * BULLET_SPEED * time.delta_seconds());
+            transform.translation += delta;
+            transform.translation = arena.wrap(transform.translation);
+        }
+    }
+}
+
+pub const BULLET_RADIUS: f32 = 0.5;
+/// Big enough that most hit tests only need to look at neighbouring cells
+pub const COLLISION_CELL_SIZE: f32 = 64.0;
+
+/// A bullet or missile as it is hit tested this frame
+pub struct Projectile {
+    pub entity: Entity,
+    pub id: BulletId,
+    pub translation: Vec3,
+    /// How far it moves over the step
+    pub step: Vec3,
+    pub owner: usize,
+    pub damage: f32,
+}
+
+/// Every bullet and missile in [`BulletId`] order, which is the same on every peer
+pub fn projectiles<'a>(
+    bullets: impl Iterator<Item = (Entity, &'a BulletId, &'a Transform, &'a BulletOwner, &'a Damage)>,
+    missiles: impl Iterator<Item = (Entity, &'a BulletId, &'a Transform, &'a Missile, &'a Damage)>,
+    delta_seconds: f32,
+) -> Vec<Projectile> {
+    let bullets = bullets.map(|(entity, id, transform, owner, damage)| Projectile {
+        entity,
+        id: *id,
+        translation: transform.translation,
+        step: transform.rotation * (Vec3::Z * BULLET_SPEED * delta_seconds),
+        owner: owner.handle,
+        damage: damage.0,
+    });
+    let missiles = missiles.map(|(entity, id, transform, missile, damage)| Projectile {
+        entity,
+        id: *id,
+        translation: transform.translation,
+        step: transform.rotation * (Vec3::Z * MISSILE_SPEED * delta_seconds),
+        owner: missile.owner,
+        damage: damage.0,
+    });
+    let mut projectiles: Vec<_> = bullets.chain(missiles).collect();
+    projectiles.sort_by_key(|projectile| projectile.id);
+    projectiles
+}
+
+pub fn recharge_shields(
+    mut shields: Query<(&mut Shield, &ShipClass)>,
+    frame: Res<SimulationFrame>,
+    ship_defs: Res<ShipDefs>,
+    time: Res<Time>,
+) {
+    for (mut shield, class) in &mut shields {
+        if frame.0.wrapping_sub(shield.last_hit_frame) >= SHIELD_RECHARGE_DELAY_FRAMES {
+            let max_shield = ship_defs.get(class.0).max_shield;
+            shield.charge = (shield.charge + SHIELD_RECHARGE_RATE * time.delta_seconds()).min(max_shield);
+        }
+    }
+}
+
+/// Takes damage off the shield first and the hull after
+pub fn apply_damage(health: &mut Health, shield: &mut Shield, damage: f32, frame: u32) {
+    let absorbed = damage.min(shield.charge);
+    shield.charge -= absorbed;
+    shield.last_hit_frame = frame;
+    health.0 -= damage - absorbed;
+}
+
+/// Applies bullet and missile hits to shields and then hulls, consuming the
+/// projectiles, and destroys ships whose hull is gone
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn kill_players(
+    mut commands: Commands,
+    mut players: Query<(Entity, &Transform, &Velocity, &Player, &ShipClass, &mut Health, &mut Shield), Without<Bullet>>,
+    bullets: Query<(Entity, &BulletId, &Transform, &BulletOwner, &Damage), With<Bullet>>,
+    missiles: Query<(Entity, &BulletId, &Transform, &Missile, &Damage)>,
+    mut next_state: ResMut<NextState<RollbackState>>,
+    mut scores: ResMut<Scores>,
+    frame: Res<SimulationFrame>,
+    arena: Res<Arena>,
+    ship_defs: Res<ShipDefs>,
+    time: Res<Time>,
+) {
+    // entity order differs between peers, and which ship a bullet hits
+    // first has to be the same everywhere
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(_, _, _, player, ..)| player.handle);
+    let projectiles = projectiles(bullets.iter(), missiles.iter(), time.delta_seconds());
+
+    let max_step = BULLET_SPEED.max(MISSILE_SPEED) * time.delta_seconds();
+    let mut grid = SpatialGrid::new(arena.grid_period(), COLLISION_CELL_SIZE);
+    for (i, projectile) in projectiles.iter().enumerate() {
+        grid.insert(projectile.translation, i);
+    }
+
+    let mut num_alive = players.len();
+    let mut anyone_died = false;
+    let mut spent = vec![false; projectiles.len()];
+    for (player_entity, player_transform, velocity, player, class, health, shield) in &mut players {
+        let mut killer = None;
+        let radius = ship_defs.get(class.0).radius;
+        // anything that could have come within reach during this step
+        let reach = radius + BULLET_RADIUS + max_step + velocity.0.length() * time.delta_seconds();
+        let candidates = if health.0 > 0.0 { grid.query(player_transform.translation, reach) } else { Vec::new() };
+        for i in candidates {
+            let projectile = &projectiles[i];
+            if spent[i] || projectile.owner == player.handle {
+                continue;
+            }
+            // test the whole step both moved over, so fast bullets can't
+            // tunnel through ships between frames
+            let Some(hit_point) = arena.swept_point_sphere_hit(
+                player_transform.translation,
+                velocity.0 * time.delta_seconds(),
+                radius + BULLET_RADIUS,
+                projectile.translation,
+                projectile.step,
+            ) else {
+                continue;
+            };
+            spent[i] = true;
+            commands.entity(projectile.entity).despawn_recursive();
+
+            apply_damage(health, shield, projectile.damage, frame.0);
+            if health.0 <= 0.0 {
+                killer = Some((projectile.owner, hit_point));
+                break;
+            }
+        }
+        // hulls can also have been wrecked earlier in the frame, by crashing
+        if health.0 > 0.0 {
+            continue;
+        }
+        commands.entity(*player_entity).despawn_recursive();
+        num_alive -= 1;
+        anyone_died = true;
+        match killer {
+            Some((killer, hit_point)) => {
+                scores.add_point(killer);
+                info!("player {} killed by player {killer} at {hit_point}: {scores:?}", player.handle);
+            }
+            None => info!("player {} crashed: {scores:?}", player.handle),
+        }
+    }
+    // the round ends once there is at most one pilot left standing
+    if anyone_died && num_alive <= 1 {
+        next_state.set(RollbackState::RoundEnd);
+    }
+}
+
+pub fn tick_simulation_frame(mut frame: ResMut<SimulationFrame>) {
+    frame.0 += 1;
+}
+
+pub fn round_end_timeout(
+    mut timer: ResMut<RoundEndTimer>,
+    mut state: ResMut<NextState<RollbackState>>,
+    time: Res<Time>,
+) {
+    timer.tick(time.delta());
+
+    if timer.just_finished() {
+        state.set(RollbackState::InRound);
+    }
+}
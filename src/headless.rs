@@ -0,0 +1,168 @@
+use crate::{arena::Arena, args::Args, bots::*, config::*, gameplay::*, replay::*, ships::ShipDefs};
+use bevy::{app::{AppExit, ScheduleRunnerPlugin}, log::LogPlugin, prelude::*, utils::HashMap};
+use bevy_ggrs::{prelude::*, LocalInputs, LocalPlayers};
+use std::time::Duration;
+
+/// Runs a match with no window or renderer. Every player is local, and
+/// either idle, flown by a bot or driven by a replay, and the simulation is stepped through
+/// a synctest session, until `--frames` frames have been simulated if given.
+pub fn run_headless(args: Args, game_config: GameConfig, replay: Option<ReplayPlayback>, log: StartupLog) {
+    let mut app = headless_app(args, game_config, replay);
+    app
+        .add_plugins(LogPlugin::default())
+        .insert_resource(log)
+        .add_systems(Startup, flush_startup_log);
+    app.run();
+}
+
+/// The app [`run_headless`] runs, without a log so tests can build several
+fn headless_app(args: Args, game_config: GameConfig, replay: Option<ReplayPlayback>) -> App {
+    // there's no asset server to load ship definitions with
+    let ship_defs = ShipDefs::read("assets").unwrap_or_else(|e| panic!("failed to read ship definitions: {e}"));
+
+    let mut app = App::new();
+    app
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0))),
+            GameplayPlugin,
+            ReplayPlugin,
+        ))
+        .insert_resource(game_config.flight_assist)
+        .insert_resource(replay.as_ref().map_or(Arena::new(args.arena, args.arena_size), |replay| replay.header.arena))
+        .insert_resource(args)
+        .insert_resource(game_config)
+        .insert_resource(ship_defs)
+        .add_systems(Startup, start_headless_session)
+        .add_systems(Update, stop_after_frames)
+        .add_systems(
+            ReadInputs,
+            (
+                read_idle_inputs.run_if(not(resource_exists::<ReplayPlayback>())),
+                drive_bots.after(read_idle_inputs).run_if(resource_exists::<Bots>()),
+            ),
+        );
+
+    if let Some(replay) = replay {
+        app.insert_resource(replay);
+    }
+
+    app
+}
+
+fn start_headless_session(
+    mut commands: Commands,
+    args: Res<Args>,
+    game_config: Res<GameConfig>,
+    ship_defs: Res<ShipDefs>,
+    arena: Res<Arena>,
+    replay: Option<Res<ReplayPlayback>>,
+) {
+    info!("Starting headless session");
+
+    // replayed inputs were recorded after the delay was applied
+    let input_delay = if replay.is_some() { 0 } else { game_config.input_delay };
+
+    let num_players = game_config.num_players;
+    let asteroid_seed = replay.as_ref().map_or(args.seed, |replay| replay.header.asteroid_seed);
+    let ships = replay.as_ref().map_or_else(
+        || vec![ship_defs.name_of(game_config.ship.as_deref()); num_players],
+        |replay| replay.header.ships.clone(),
+    );
+    // a replay could come from a build with other ships
+    let choices = ship_defs.choices(&ships).unwrap_or_else(|e| panic!("can't fly the recorded ships: {e}"));
+    reset_simulation(&mut commands, num_players, asteroid_seed, choices);
+    commands.insert_resource(new_synctest_session(num_players, input_delay, args.check_distance));
+    start_recording(&mut commands, &args, num_players, input_delay, asteroid_seed, game_config.flight_assist, *arena, &ships);
+    if replay.is_none() && game_config.bots > 0 {
+        commands.insert_resource(Bots::new(num_players - game_config.bots..num_players, game_config.bot_difficulty));
+    }
+}
+
+fn read_idle_inputs(
+    mut commands: Commands,
+    local_players: Res<LocalPlayers>,
+) {
+    let mut local_inputs = HashMap::new();
+    for handle in &local_players.0 {
+        local_inputs.insert(*handle, [0u8; 3]);
+    }
+    commands.insert_resource(LocalInputs::<Config>(local_inputs));
+}
+
+/// Ends the match once `--frames` frames have been simulated
+fn stop_after_frames(
+    args: Res<Args>,
+    frame: Res<SimulationFrame>,
+    scores: Res<Scores>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(frames) = args.frames else { return; };
+    if frame.0 >= frames {
+        info!("stopping after {} frames, scores: {:?}", frame.0, scores.0);
+        exit.send(AppExit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Player;
+    use bevy::time::TimeUpdateStrategy;
+    use clap::Parser;
+
+    const FRAMES: u32 = 240;
+
+    /// Where every ship was after each simulated frame, by handle, and the scores
+    type Snapshot = (Vec<(usize, Vec3)>, Vec<u32>);
+
+    #[derive(Resource, Default)]
+    struct Snapshots(HashMap<u32, Snapshot>);
+
+    fn snapshot(
+        mut snapshots: ResMut<Snapshots>,
+        frame: Res<SimulationFrame>,
+        scores: Res<Scores>,
+        players: Query<(&Player, &Transform)>,
+    ) {
+        let mut ships: Vec<_> = players.iter().map(|(player, transform)| (player.handle, transform.translation)).collect();
+        ships.sort_by_key(|(handle, _)| *handle);
+        // resimulated frames replace what was there, which has to be the same
+        snapshots.0.insert(frame.0, (ships, scores.0.clone()));
+    }
+
+    /// Steps a headless match until it stops itself, returning how it stood
+    /// after `FRAMES` frames
+    fn run(flags: &[&str]) -> Snapshot {
+        let frames = (FRAMES + 10).to_string();
+        let args = Args::parse_from(["flying_shooter", "--headless", "--frames", frames.as_str()].iter().chain(flags));
+        let game_config = GameConfig::load(&args, &mut StartupLog::default());
+        let mut app = headless_app(args, game_config, None);
+        app
+            .init_resource::<Snapshots>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / 50.0)))
+            .add_systems(GgrsSchedule, snapshot.after(tick_simulation_frame));
+
+        let mut updates = 0;
+        while app.world.resource::<Events<AppExit>>().is_empty() {
+            app.update();
+            updates += 1;
+            assert!(updates < FRAMES * 10, "the match never stopped");
+        }
+        app.world.resource::<Snapshots>().0.get(&FRAMES).cloned().expect("frame was simulated")
+    }
+
+    #[test]
+    fn headless_matches_stop_after_the_given_frames() {
+        let (ships, scores) = run(&["--players", "2"]);
+        assert_eq!(ships.len(), 2);
+        assert_eq!(scores, vec![0, 0]);
+    }
+
+    #[test]
+    fn headless_matches_with_bots_are_deterministic() {
+        let flags = ["--players", "3", "--bots", "2", "--seed", "7"];
+        let (ships, scores) = run(&flags);
+        assert_eq!(ships.len(), 3);
+        assert_eq!(run(&flags), (ships, scores));
+    }
+}
@@ -1,6 +1,7 @@
-use crate::{components::Player, game::{ButtonAction, Config}};
-use bevy::{prelude::*, utils::HashMap};
+use crate::{components::Player, config::GameConfig, game::ButtonAction, gameplay::Config};
+use bevy::{ecs::system::SystemParam, input::gamepad::GamepadConnectionEvent, prelude::*, utils::HashMap};
 use bevy_ggrs::{LocalInputs, LocalPlayers};
+use serde::Deserialize;
 use virtual_joystick::*;
 
 const INPUT_UP: u8 = 1 << 0;
@@ -8,13 +9,206 @@ const INPUT_DOWN: u8 = 1 << 1;
 const INPUT_LEFT: u8 = 1 << 2;
 const INPUT_RIGHT: u8 = 1 << 3;
 const INPUT_FIRE: u8 = 1 << 4;
+const INPUT_THROTTLE_UP: u8 = 1 << 5;
+const INPUT_THROTTLE_DOWN: u8 = 1 << 6;
+const INPUT_MISSILE: u8 = 1 << 7;
 
-const PITCH_SPEED: f32 = 100.0;
-const ROLL_SPEED: f32 = 200.0;
 
+/// Keys for each action, any of them will do. Can be overridden per action
+/// from the config file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub pitch_up: Vec<KeyCode>,
+    pub pitch_down: Vec<KeyCode>,
+    pub roll_left: Vec<KeyCode>,
+    pub roll_right: Vec<KeyCode>,
+    pub fire: Vec<KeyCode>,
+    pub throttle_up: Vec<KeyCode>,
+    pub throttle_down: Vec<KeyCode>,
+    pub missile: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            pitch_up: vec![KeyCode::Up, KeyCode::W],
+            pitch_down: vec![KeyCode::Down, KeyCode::S],
+            roll_left: vec![KeyCode::Left, KeyCode::A],
+            roll_right: vec![KeyCode::Right, KeyCode::D],
+            fire: vec![KeyCode::Space, KeyCode::Return],
+            throttle_up: vec![KeyCode::E, KeyCode::ShiftLeft],
+            throttle_down: vec![KeyCode::Q, KeyCode::ControlLeft],
+            missile: vec![KeyCode::F, KeyCode::AltLeft],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The keys on the left of the keyboard, for the first split-screen pilot
+    pub fn left_hand() -> Self {
+        KeyBindings {
+            pitch_up: vec![KeyCode::W],
+            pitch_down: vec![KeyCode::S],
+            roll_left: vec![KeyCode::A],
+            roll_right: vec![KeyCode::D],
+            fire: vec![KeyCode::Space],
+            throttle_up: vec![KeyCode::E, KeyCode::ShiftLeft],
+            throttle_down: vec![KeyCode::Q, KeyCode::ControlLeft],
+            missile: vec![KeyCode::F, KeyCode::AltLeft],
+        }
+    }
+
+    /// The arrow keys and the ones around them, for the second split-screen pilot
+    pub fn right_hand() -> Self {
+        KeyBindings {
+            pitch_up: vec![KeyCode::Up],
+            pitch_down: vec![KeyCode::Down],
+            roll_left: vec![KeyCode::Left],
+            roll_right: vec![KeyCode::Right],
+            fire: vec![KeyCode::Return, KeyCode::Numpad0],
+            throttle_up: vec![KeyCode::ShiftRight],
+            throttle_down: vec![KeyCode::ControlRight],
+            missile: vec![KeyCode::AltRight, KeyCode::NumpadDecimal],
+        }
+    }
+}
+
+/// How gamepads fly, from the config file. The left stick pitches and rolls,
+/// the right trigger fires, the left trigger opens the throttle, the left
+/// bumper closes it and the right bumper launches missiles.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GamepadConfig {
+    /// Stick travel, from 0 to 1, that still counts as centred
+    pub deadzone: f32,
+    /// How far, from 0 to 1, a trigger has to be pulled to count
+    pub trigger_threshold: f32,
+    /// Push the stick to raise the nose rather than lower it
+    pub invert_pitch: bool,
+    pub invert_roll: bool,
+    /// Local pilot each gamepad flies, in the order they're plugged in.
+    /// Gamepads past the end of the list go to the first pilot without one.
+    pub pilots: Vec<usize>,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        GamepadConfig {
+            deadzone: 0.15,
+            trigger_threshold: 0.5,
+            invert_pitch: false,
+            invert_roll: false,
+            pilots: Vec::new(),
+        }
+    }
+}
+
+/// The local pilot each connected gamepad flies, in the order they were
+/// plugged in
+#[derive(Resource, Debug, Default)]
+pub struct GamepadPilots(Vec<(Gamepad, usize)>);
+
+/// Hands gamepads to local pilots as they're plugged in, and takes them back
+/// when they're unplugged
+pub fn assign_gamepads(
+    mut connections: EventReader<GamepadConnectionEvent>,
+    mut gamepad_pilots: ResMut<GamepadPilots>,
+    game_config: Res<GameConfig>,
+) {
+    for connection in connections.read() {
+        let assigned = &mut gamepad_pilots.0;
+        if connection.disconnected() {
+            if let Some(index) = assigned.iter().position(|(gamepad, _)| *gamepad == connection.gamepad) {
+                let (_, pilot) = assigned.remove(index);
+                info!("gamepad {} of pilot {} unplugged", connection.gamepad.id, pilot + 1);
+            }
+            continue;
+        }
+        if assigned.iter().any(|(gamepad, _)| *gamepad == connection.gamepad) {
+            continue;
+        }
+        let pilots = game_config.local_players;
+        let pilot = game_config
+            .gamepad
+            .pilots
+            .get(assigned.len())
+            .copied()
+            .filter(|pilot| *pilot < pilots)
+            .or_else(|| (0..pilots).find(|pilot| !assigned.iter().any(|(_, taken)| taken == pilot)))
+            .unwrap_or(0);
+        info!("gamepad {} flies pilot {}", connection.gamepad.id, pilot + 1);
+        assigned.push((connection.gamepad, pilot));
+    }
+}
+
+/// The state of every gamepad, and who flies with which
+#[derive(SystemParam)]
+pub struct GamepadInput<'w> {
+    gamepad_pilots: Res<'w, GamepadPilots>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    button_axes: Res<'w, Axis<GamepadButton>>,
+}
+
+impl GamepadInput<'_> {
+    /// Adds what the `pilot`th local pilot's gamepads are doing to `input`
+    fn read(&self, pilot: usize, config: &GamepadConfig, input: &mut [u8; 3]) {
+        let gamepads = self.gamepad_pilots.0.iter().filter(|(_, flies)| *flies == pilot).map(|(gamepad, _)| *gamepad);
+        for gamepad in gamepads {
+            let axis = |axis_type| self.axes.get(GamepadAxis::new(gamepad, axis_type)).unwrap_or(0.0);
+            let trigger = |button_type| {
+                self.button_axes.get(GamepadButton::new(gamepad, button_type)).unwrap_or(0.0) >= config.trigger_threshold
+            };
+            let pressed = |button_type| self.buttons.pressed(GamepadButton::new(gamepad, button_type));
+
+            let stick = shape_stick(Vec2::new(axis(GamepadAxisType::LeftStickX), axis(GamepadAxisType::LeftStickY)), config);
+            // pushing the stick lowers the nose, like the on-screen joystick
+            if stick != Vec2::ZERO {
+                input[1] = ((stick.x * 100.0).round() as i8) as u8;
+                input[2] = ((stick.y * 100.0).round() as i8) as u8;
+            }
+
+            if trigger(GamepadButtonType::RightTrigger2) {
+                input[0] |= INPUT_FIRE;
+            }
+            if trigger(GamepadButtonType::LeftTrigger2) {
+                input[0] |= INPUT_THROTTLE_UP;
+            }
+            if pressed(GamepadButtonType::LeftTrigger) {
+                input[0] |= INPUT_THROTTLE_DOWN;
+            }
+            if pressed(GamepadButtonType::RightTrigger) {
+                input[0] |= INPUT_MISSILE;
+            }
+        }
+    }
+}
+
+/// Roll and pitch stick from where the left stick is: nothing inside the
+/// deadzone, and rescaled past it so the stick still reaches full travel
+fn shape_stick(stick: Vec2, config: &GamepadConfig) -> Vec2 {
+    let travel = stick.length();
+    let mut stick = if travel <= config.deadzone {
+        Vec2::ZERO
+    } else {
+        stick / travel * ((travel - config.deadzone) / (1.0 - config.deadzone)).min(1.0)
+    };
+    if config.invert_roll {
+        stick.x = -stick.x;
+    }
+    if config.invert_pitch {
+        stick.y = -stick.y;
+    }
+    stick
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn read_local_inputs(
     mut commands: Commands,
     keys: Res<Input<KeyCode>>,
+    gamepads: GamepadInput,
+    game_config: Res<GameConfig>,
     players: Query<&Player>,
     local_players: Option<Res<LocalPlayers>>,
     mut joystick: EventReader<VirtualJoystickEvent<String>>,
@@ -33,7 +227,7 @@ pub fn read_local_inputs(
         handles.push(0);
     }
     let mut local_inputs = HashMap::new();
-    for handle in &handles {
+    for (pilot, handle) in handles.iter().enumerate() {
         {
             let mut input: [u8; 3] = [0u8; 3];
             for j in joystick.read() {
@@ -49,25 +243,42 @@ pub fn read_local_inputs(
                     input[2] = ((axis.y * 100.0).round() as i8) as u8;
                 }
             }
-            if keys.any_pressed([KeyCode::Up, KeyCode::W]) {
-                input[0] |= INPUT_UP;
-            }
-            if keys.any_pressed([KeyCode::Down, KeyCode::S]) {
-                input[0] |= INPUT_DOWN;
-            }
-            if keys.any_pressed([KeyCode::Left, KeyCode::A]) {
-                input[0] |= INPUT_LEFT
-            }
-            if keys.any_pressed([KeyCode::Right, KeyCode::D]) {
-                input[0] |= INPUT_RIGHT;
-            }
-            if keys.any_pressed([KeyCode::Space, KeyCode::Return]) {
-                input[0] |= INPUT_FIRE;
+            // local handles past the split-screen pilots are left idle
+            if let Some(bindings) = game_config.key_bindings_for(pilot) {
+                if keys.any_pressed(bindings.pitch_up.iter().copied()) {
+                    input[0] |= INPUT_UP;
+                }
+                if keys.any_pressed(bindings.pitch_down.iter().copied()) {
+                    input[0] |= INPUT_DOWN;
+                }
+                if keys.any_pressed(bindings.roll_left.iter().copied()) {
+                    input[0] |= INPUT_LEFT
+                }
+                if keys.any_pressed(bindings.roll_right.iter().copied()) {
+                    input[0] |= INPUT_RIGHT;
+                }
+                if keys.any_pressed(bindings.fire.iter().copied()) {
+                    input[0] |= INPUT_FIRE;
+                }
+                if keys.any_pressed(bindings.throttle_up.iter().copied()) {
+                    input[0] |= INPUT_THROTTLE_UP;
+                }
+                if keys.any_pressed(bindings.throttle_down.iter().copied()) {
+                    input[0] |= INPUT_THROTTLE_DOWN;
+                }
+                if keys.any_pressed(bindings.missile.iter().copied()) {
+                    input[0] |= INPUT_MISSILE;
+                }
             }
+            // like the keys, a lone pilot's gamepads fly every local handle
+            let gamepad_pilot = if game_config.local_players == 1 { 0 } else { pilot };
+            gamepads.read(gamepad_pilot, &game_config.gamepad, &mut input);
             for (interaction, action) in &interaction_query {
                 if *interaction == Interaction::Pressed {
                     match action {
                         ButtonAction::Fire => input[0] |= INPUT_FIRE,
+                        ButtonAction::Thrust => input[0] |= INPUT_THROTTLE_UP,
+                        ButtonAction::Missile => input[0] |= INPUT_MISSILE,
                     }
                 }
             }
@@ -78,26 +289,163 @@ pub fn read_local_inputs(
     commands.insert_resource(LocalInputs::<Config>(local_inputs));
 }
 
+/// What a pilot that isn't at the keyboard, like a bot, holds down for a
+/// frame, packed into the same input bytes the keyboard and joystick make
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Controls {
+    /// -1 to 1, like [`pitch_input`]
+    pub pitch: f32,
+    /// -1 to 1, like [`roll_input`]
+    pub roll: f32,
+    /// 1 to open the throttle, -1 to close it, 0 to hold it
+    pub throttle: f32,
+    pub fire: bool,
+    pub missile: bool,
+}
+
+impl Controls {
+    pub fn to_input(self) -> [u8; 3] {
+        let mut input = [0u8; 3];
+        if self.throttle > 0.0 {
+            input[0] |= INPUT_THROTTLE_UP;
+        } else if self.throttle < 0.0 {
+            input[0] |= INPUT_THROTTLE_DOWN;
+        }
+        if self.fire {
+            input[0] |= INPUT_FIRE;
+        }
+        if self.missile {
+            input[0] |= INPUT_MISSILE;
+        }
+        input[1] = ((self.roll.clamp(-1.0, 1.0) * 100.0).round() as i8) as u8;
+        input[2] = ((self.pitch.clamp(-1.0, 1.0) * 100.0).round() as i8) as u8;
+        input
+    }
+}
+
 pub fn fire(input: [u8; 3]) -> bool {
     input[0] & INPUT_FIRE != 0
 }
 
-pub fn angular_thrust_pitch(input: [u8; 3]) -> f32 {
+pub fn fire_missile(input: [u8; 3]) -> bool {
+    input[0] & INPUT_MISSILE != 0
+}
+
+/// 1 to open the throttle, -1 to close it, 0 to hold it
+pub fn throttle_input(input: [u8; 3]) -> f32 {
+    let mut throttle = 0.0;
+    if input[0] & INPUT_THROTTLE_UP != 0 {
+        throttle += 1.0;
+    }
+    if input[0] & INPUT_THROTTLE_DOWN != 0 {
+        throttle -= 1.0;
+    }
+    throttle
+}
+
+/// -1 to 1, as a fraction of the ship's pitch speed
+pub fn pitch_input(input: [u8; 3]) -> f32 {
     if input[0] & INPUT_DOWN != 0 {
-        return -PITCH_SPEED;
+        return -1.0;
     } else if input[0] & INPUT_UP != 0 {
-        return PITCH_SPEED;
+        return 1.0;
     }
-    let joystick_y = ((input[2] as i8) as f32) / 100.0;
-    return joystick_y * PITCH_SPEED;
+    ((input[2] as i8) as f32) / 100.0
 }
 
-pub fn angular_thrust_roll(input: [u8; 3]) -> f32 {
+/// -1 to 1, as a fraction of the ship's roll speed
+pub fn roll_input(input: [u8; 3]) -> f32 {
     if input[0] & INPUT_LEFT != 0 {
-        return -ROLL_SPEED;
+        return -1.0;
     } else if input[0] & INPUT_RIGHT != 0 {
-        return ROLL_SPEED;
+        return 1.0;
+    }
+    ((input[1] as i8) as f32) / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::SystemState;
+
+    fn close(a: Vec2, b: Vec2) -> bool {
+        a.distance(b) < 1e-5
+    }
+
+    #[test]
+    fn sticks_are_rescaled_past_the_deadzone() {
+        let config = GamepadConfig::default();
+        assert_eq!(shape_stick(Vec2::new(0.1, 0.0), &config), Vec2::ZERO);
+        assert_eq!(shape_stick(Vec2::new(0.0, config.deadzone), &config), Vec2::ZERO);
+        // picks up from zero just past the deadzone rather than jumping
+        let nudged = shape_stick(Vec2::new(config.deadzone + 0.01, 0.0), &config);
+        assert!(nudged.x > 0.0 && nudged.x < 0.02, "{nudged}");
+        assert!(close(shape_stick(Vec2::new(0.0, -1.0), &config), Vec2::new(0.0, -1.0)));
+        // halfway through what's left past the deadzone is half stick
+        let halfway = (1.0 + config.deadzone) / 2.0;
+        assert!(close(shape_stick(Vec2::new(halfway, 0.0), &config), Vec2::new(0.5, 0.0)));
+        // keeps the direction, and never goes past full travel in the corners
+        let corner = shape_stick(Vec2::new(1.0, 1.0), &config);
+        assert!(close(corner, Vec2::new(1.0, 1.0).normalize()), "{corner}");
+    }
+
+    #[test]
+    fn inverted_axes_are_flipped() {
+        let stick = Vec2::new(0.6, -0.8);
+        let config = GamepadConfig { invert_pitch: true, ..default() };
+        let shaped = shape_stick(stick, &GamepadConfig::default());
+        assert!(close(shape_stick(stick, &config), Vec2::new(shaped.x, -shaped.y)));
+        let config = GamepadConfig { invert_roll: true, ..default() };
+        assert!(close(shape_stick(stick, &config), Vec2::new(-shaped.x, shaped.y)));
+    }
+
+    #[test]
+    fn gamepads_are_read_into_the_pilots_input() {
+        let gamepad = Gamepad::new(0);
+        let mut world = World::new();
+        world.insert_resource(GamepadPilots(vec![(gamepad, 1)]));
+        let mut axes = Axis::<GamepadAxis>::default();
+        axes.set(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX), 1.0);
+        axes.set(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY), 0.0);
+        world.insert_resource(axes);
+        let mut buttons = Input::<GamepadButton>::default();
+        buttons.press(GamepadButton::new(gamepad, GamepadButtonType::RightTrigger));
+        world.insert_resource(buttons);
+        let mut button_axes = Axis::<GamepadButton>::default();
+        button_axes.set(GamepadButton::new(gamepad, GamepadButtonType::RightTrigger2), 0.9);
+        button_axes.set(GamepadButton::new(gamepad, GamepadButtonType::LeftTrigger2), 0.2);
+        world.insert_resource(button_axes);
+        let mut state = SystemState::<GamepadInput>::new(&mut world);
+        let gamepads = state.get(&world);
+        let config = GamepadConfig::default();
+
+        let mut input = [0u8; 3];
+        gamepads.read(1, &config, &mut input);
+        assert_eq!(input, [INPUT_FIRE | INPUT_MISSILE, 100, 0]);
+        assert_eq!(roll_input(input), 1.0);
+
+        // someone else's gamepad leaves the input alone
+        let mut input = [INPUT_UP, 0, 0];
+        gamepads.read(0, &config, &mut input);
+        assert_eq!(input, [INPUT_UP, 0, 0]);
+    }
+
+    #[test]
+    fn controls_are_packed_like_keyboard_and_joystick_input() {
+        let input = Controls { pitch: -0.5, roll: 0.25, throttle: 1.0, fire: true, missile: false }.to_input();
+        assert_eq!(input[0], INPUT_THROTTLE_UP | INPUT_FIRE);
+        assert_eq!(pitch_input(input), -0.5);
+        assert_eq!(roll_input(input), 0.25);
+        assert_eq!(throttle_input(input), 1.0);
+        assert!(fire(input) && !fire_missile(input));
+
+        let input = Controls { pitch: 3.0, roll: -3.0, throttle: -0.1, fire: false, missile: true }.to_input();
+        assert_eq!(input[0], INPUT_THROTTLE_DOWN | INPUT_MISSILE);
+        // past full stick is full stick
+        assert_eq!(pitch_input(input), 1.0);
+        assert_eq!(roll_input(input), -1.0);
+        assert_eq!(throttle_input(input), -1.0);
+
+        assert_eq!(Controls::default().to_input(), [0, 0, 0]);
     }
-    let joystick_x = ((input[1] as i8) as f32) / 100.0;
-    return joystick_x * ROLL_SPEED;
 }
@@ -1,13 +1,27 @@
 use wasm_bindgen::prelude::wasm_bindgen;
 
+mod arena;
 mod args;
+mod asteroids;
+mod bots;
 mod components;
+mod config;
+mod desync;
+mod flight;
 mod input;
 mod game;
+mod gameplay;
 mod fps_plugin;
+mod headless;
 mod math;
+mod missiles;
 mod pbr_material;
+mod presentation;
 mod radar;
+mod replay;
+mod ships;
+mod spectator;
+mod spatial_grid;
 
 #[wasm_bindgen]
 pub fn run_game() {
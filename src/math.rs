@@ -1,7 +1,52 @@
 use bevy::math::Vec3;
 
-const FINITE_CUBE_SIZE: f32 = 1024.0 * 4.0;
+mod torus;
 
+pub use torus::Torus;
+
+pub const FINITE_CUBE_SIZE: f32 = 1024.0 * 4.0;
+
+/// The SplitMix64 finalizer, which spreads every bit of `z` over the result.
+/// Unlike a hasher's, its output can't change with a crate version, so
+/// everything the simulation or bots derive from it stays the same.
+pub fn split_mix(z: u64) -> u64 {
+    let z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Where along the segment from `start` to `end` it first touches the sphere,
+/// as a fraction from 0 to 1. A segment starting inside the sphere touches it at 0.
+pub fn segment_sphere_intersection(start: Vec3, end: Vec3, center: Vec3, radius: f32) -> Option<f32> {
+    let d = end - start;
+    let f = start - center;
+    let c = f.length_squared() - radius * radius;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    let a = d.length_squared();
+    let b = f.dot(d);
+    // not moving, or moving away from the sphere
+    if a == 0.0 || b >= 0.0 {
+        return None;
+    }
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / a;
+    if t <= 1.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// The wrapping functions `Torus` replaced, kept as they were to check it
+// against. They brute force the closest image out of all 27 neighbours.
+
+#[cfg(test)]
 pub fn warp_infinite_space_into_finite_cube(p: Vec3) -> Vec3 {
     let mut x = p.x % FINITE_CUBE_SIZE;
     let mut y = p.y % FINITE_CUBE_SIZE;
@@ -9,9 +54,10 @@ pub fn warp_infinite_space_into_finite_cube(p: Vec3) -> Vec3 {
     if x < 0.0 { x += FINITE_CUBE_SIZE; }
     if y < 0.0 { y += FINITE_CUBE_SIZE; }
     if z < 0.0 { z += FINITE_CUBE_SIZE; }
-    return Vec3::new(x, y, z);
+    Vec3::new(x, y, z)
 }
 
+#[cfg(test)]
 pub fn finite_cube_point_to_closest_visible_location(observer: Vec3, pt: Vec3) -> Vec3 {
     let mut closest = pt;
     let mut closest_dist = observer.distance_squared(pt);
@@ -30,5 +76,44 @@ pub fn finite_cube_point_to_closest_visible_location(observer: Vec3, pt: Vec3) -
             }
         }
     }
-    return closest;
-}
\ No newline at end of file
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_hit_where_they_first_touch() {
+        let t = segment_sphere_intersection(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(5.0, 0.0, 0.0), Vec3::ZERO, 1.0);
+        assert_eq!(t, Some(0.4));
+    }
+
+    #[test]
+    fn tangent_segments_touch_once() {
+        let t = segment_sphere_intersection(Vec3::new(-5.0, 1.0, 0.0), Vec3::new(5.0, 1.0, 0.0), Vec3::ZERO, 1.0);
+        assert_eq!(t, Some(0.5));
+        let t = segment_sphere_intersection(Vec3::new(-5.0, 1.01, 0.0), Vec3::new(5.0, 1.01, 0.0), Vec3::ZERO, 1.0);
+        assert_eq!(t, None);
+    }
+
+    #[test]
+    fn segments_starting_inside_touch_at_the_start() {
+        let t = segment_sphere_intersection(Vec3::new(0.5, 0.0, 0.0), Vec3::new(5.0, 0.0, 0.0), Vec3::ZERO, 1.0);
+        assert_eq!(t, Some(0.0));
+    }
+
+    #[test]
+    fn segments_stopping_short_miss() {
+        let t = segment_sphere_intersection(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(-3.0, 0.0, 0.0), Vec3::ZERO, 1.0);
+        assert_eq!(t, None);
+    }
+
+    #[test]
+    fn zero_length_segments_only_touch_from_inside() {
+        let outside = Vec3::new(2.0, 0.0, 0.0);
+        assert_eq!(segment_sphere_intersection(outside, outside, Vec3::ZERO, 1.0), None);
+        let inside = Vec3::new(0.5, 0.0, 0.0);
+        assert_eq!(segment_sphere_intersection(inside, inside, Vec3::ZERO, 1.0), Some(0.0));
+    }
+}
@@ -0,0 +1,290 @@
+use super::FINITE_CUBE_SIZE;
+use bevy::prelude::*;
+
+/// A cube of space whose opposite faces are joined, so anything leaving
+/// through one face comes back in through the other. Every point has an
+/// image every `size` units along each axis, and these all work with
+/// whichever images are closest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Torus {
+    pub size: f32,
+}
+
+impl Default for Torus {
+    fn default() -> Self {
+        Torus::new(FINITE_CUBE_SIZE)
+    }
+}
+
+impl Torus {
+    pub fn new(size: f32) -> Self {
+        Torus { size }
+    }
+
+    /// The image of `p` inside the cube, from 0 up to but not including `size`
+    pub fn wrap(self, p: Vec3) -> Vec3 {
+        let wrap = |x: f32| {
+            let x = x.rem_euclid(self.size);
+            // tiny negative values round up to size
+            if x >= self.size { 0.0 } else { x }
+        };
+        Vec3::new(wrap(p.x), wrap(p.y), wrap(p.z))
+    }
+
+    /// Shortest vector from `from` to any image of `to`
+    pub fn displacement(self, from: Vec3, to: Vec3) -> Vec3 {
+        let d = to - from;
+        d - (d / self.size).round() * self.size
+    }
+
+    pub fn distance(self, a: Vec3, b: Vec3) -> f32 {
+        self.displacement(a, b).length()
+    }
+
+    pub fn distance_squared(self, a: Vec3, b: Vec3) -> f32 {
+        self.displacement(a, b).length_squared()
+    }
+
+    /// The image of `p` closest to `observer`, which may lie outside the cube
+    pub fn closest_image(self, observer: Vec3, p: Vec3) -> Vec3 {
+        p - ((p - observer) / self.size).round() * self.size
+    }
+
+    /// Every image of `p` within `radius` of `observer`, nearest first. The
+    /// closest image is always included, even when it's further away.
+    pub fn images_within(self, observer: Vec3, p: Vec3, radius: f32) -> Vec<Vec3> {
+        let closest = self.closest_image(observer, p);
+        let reach = (radius / self.size).ceil() as i32 + 1;
+        let mut images = vec![closest];
+        for x in -reach..=reach {
+            for y in -reach..=reach {
+                for z in -reach..=reach {
+                    if x == 0 && y == 0 && z == 0 {
+                        continue;
+                    }
+                    let image = closest + Vec3::new(x as f32, y as f32, z as f32) * self.size;
+                    if observer.distance_squared(image) <= radius * radius {
+                        images.push(image);
+                    }
+                }
+            }
+        }
+        images[1..].sort_by(|a, b| observer.distance_squared(*a).total_cmp(&observer.distance_squared(*b)));
+        images
+    }
+
+    /// Moves from `a` towards `b` the short way round, crossing the seam if
+    /// that's shorter, and wraps the result back into the cube
+    pub fn lerp(self, a: Vec3, b: Vec3, t: f32) -> Vec3 {
+        self.wrap(a + self.displacement(a, b) * t)
+    }
+
+    /// Interpolates a transform, its translation the short way round
+    pub fn lerp_transform(self, a: &Transform, b: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.lerp(a.translation, b.translation, t),
+            rotation: a.rotation.slerp(b.rotation, t),
+            scale: a.scale.lerp(b.scale, t),
+        }
+    }
+
+    /// Where along the segment from `start` to `start + delta` it first
+    /// touches the image of the sphere closest to the segment, as a fraction
+    /// from 0 to 1. Segments are expected to be shorter than half the cube.
+    pub fn segment_sphere_intersection(self, start: Vec3, delta: Vec3, center: Vec3, radius: f32) -> Option<f32> {
+        let center = self.closest_image(start + delta * 0.5, center);
+        super::segment_sphere_intersection(start, start + delta, center, radius)
+    }
+
+    /// Distance along a ray to where it first touches the sphere, looking no
+    /// further than `max_distance`. `direction` has to be normalized.
+    // nothing casts rays through the arena yet
+    #[allow(dead_code)]
+    pub fn ray_sphere_intersection(
+        self,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        center: Vec3,
+        radius: f32,
+    ) -> Option<f32> {
+        self.segment_sphere_intersection(origin, direction * max_distance, center, radius)
+            .map(|t| t * max_distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{finite_cube_point_to_closest_visible_location, warp_infinite_space_into_finite_cube};
+
+    const SIZE: f32 = FINITE_CUBE_SIZE;
+
+    /// Same points every run, spread over a few cubes either side of the origin
+    fn points(count: usize, extent: f32) -> Vec<Vec3> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 40) as f32 / (1u64 << 24) as f32 * 2.0 - 1.0) * extent
+        };
+        let mut points: Vec<Vec3> = (0..count).map(|_| Vec3::new(next(), next(), next())).collect();
+        // on and just either side of the seams
+        points.extend([
+            Vec3::ZERO,
+            Vec3::splat(SIZE),
+            Vec3::splat(-SIZE),
+            Vec3::new(-0.001, SIZE - 0.001, SIZE + 0.001),
+            Vec3::new(-1.0, -SIZE - 1.0, 2.0 * SIZE + 1.0),
+            Vec3::new(0.5 * SIZE, -0.5 * SIZE, 1.5 * SIZE),
+        ]);
+        points
+    }
+
+    /// Whether `a` and `b` are the same point, allowing for one being on the
+    /// far side of a seam the other is on the near side of
+    fn same_image(a: Vec3, b: Vec3) -> bool {
+        (a - b).to_array().iter().all(|d| d.abs() < 1e-2 || (d.abs() - SIZE).abs() < 1e-2)
+    }
+
+    #[test]
+    fn wrap_matches_the_old_warp() {
+        let torus = Torus::default();
+        for p in points(1000, 3.0 * SIZE) {
+            let wrapped = torus.wrap(p);
+            assert!(wrapped.cmpge(Vec3::ZERO).all() && wrapped.cmplt(Vec3::splat(SIZE)).all(), "{p} wrapped to {wrapped}");
+            let old = warp_infinite_space_into_finite_cube(p);
+            assert!(same_image(wrapped, old), "{p} wrapped to {wrapped}, used to be {old}");
+        }
+    }
+
+    #[test]
+    fn closest_image_matches_the_brute_force_search() {
+        let torus = Torus::default();
+        let points: Vec<Vec3> = points(200, 3.0 * SIZE).into_iter().map(|p| torus.wrap(p)).collect();
+        for (observer, p) in points.iter().zip(points.iter().rev()) {
+            let closest = torus.closest_image(*observer, *p);
+            let old = finite_cube_point_to_closest_visible_location(*observer, *p);
+            // ties halfway across can go either way, but are as close
+            let (distance, old_distance) = (observer.distance(closest), observer.distance(old));
+            assert!((distance - old_distance).abs() < 1e-2, "{p} seen from {observer}: {closest} rather than {old}");
+            let offset = (closest - *p) / SIZE;
+            assert!(offset.distance(offset.round()) < 1e-4, "{closest} isn't an image of {p}");
+        }
+    }
+
+    #[test]
+    fn images_within_finds_every_image_in_range() {
+        let torus = Torus::default();
+        let points: Vec<Vec3> = points(50, 3.0 * SIZE).into_iter().map(|p| torus.wrap(p)).collect();
+        for radius in [0.0, 100.0, 0.5 * SIZE, 1.5 * SIZE] {
+            for (observer, p) in points.iter().zip(points.iter().skip(1)) {
+                let images = torus.images_within(*observer, *p, radius);
+                let closest = finite_cube_point_to_closest_visible_location(*observer, *p);
+                assert!(
+                    (observer.distance(images[0]) - observer.distance(closest)).abs() < 1e-2,
+                    "images of {p} seen from {observer} don't start with the closest"
+                );
+
+                let mut expected = vec![images[0]];
+                for x in -4..=4 {
+                    for y in -4..=4 {
+                        for z in -4..=4 {
+                            let image = images[0] + Vec3::new(x as f32, y as f32, z as f32) * SIZE;
+                            if (x, y, z) != (0, 0, 0) && observer.distance_squared(image) <= radius * radius {
+                                expected.push(image);
+                            }
+                        }
+                    }
+                }
+                let mut images = images.clone();
+                let by_position = |a: &Vec3, b: &Vec3| a.to_array().partial_cmp(&b.to_array()).unwrap();
+                images.sort_by(by_position);
+                expected.sort_by(by_position);
+                assert_eq!(images, expected, "images of {p} within {radius} of {observer}");
+            }
+        }
+    }
+
+    /// Pairs of points in the cube, the old functions only handle those
+    fn pairs() -> Vec<(Vec3, Vec3)> {
+        let torus = Torus::default();
+        let points: Vec<Vec3> = points(200, 3.0 * SIZE).into_iter().map(|p| torus.wrap(p)).collect();
+        points.iter().copied().zip(points.iter().rev().copied()).collect()
+    }
+
+    #[test]
+    fn displacement_and_distance_match_the_brute_force_search() {
+        let torus = Torus::default();
+        for (a, b) in pairs() {
+            let old = finite_cube_point_to_closest_visible_location(a, b) - a;
+            let displacement = torus.displacement(a, b);
+            assert!((displacement.length() - old.length()).abs() < 1e-2, "{a} to {b}: {displacement} rather than {old}");
+            assert!(same_image(a + displacement, b), "{a} plus {displacement} isn't an image of {b}");
+            assert!((torus.distance(a, b) - old.length()).abs() < 1e-2);
+            assert!((torus.distance_squared(a, b).sqrt() - old.length()).abs() < 1e-2);
+            assert!((torus.distance(a, b) - torus.distance(b, a)).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn lerp_goes_the_short_way_round() {
+        let torus = Torus::default();
+        for (a, b) in pairs() {
+            let old = finite_cube_point_to_closest_visible_location(a, b);
+            for t in [0.0, 0.25, 0.5, 1.0] {
+                let lerped = torus.lerp(a, b, t);
+                let expected = warp_infinite_space_into_finite_cube(a.lerp(old, t));
+                assert!(same_image(lerped, expected), "{a} to {b} at {t}: {lerped} rather than {expected}");
+            }
+        }
+        // across the seam rather than back through the whole cube
+        let lerped = torus.lerp(Vec3::new(SIZE - 6.0, 10.0, 10.0), Vec3::new(10.0, 10.0, 10.0), 0.5);
+        assert!(lerped.distance(Vec3::new(2.0, 10.0, 10.0)) < 1e-3, "{lerped}");
+    }
+
+    #[test]
+    fn transforms_are_interpolated_across_the_seam() {
+        let torus = Torus::default();
+        let a = Transform::from_xyz(SIZE - 6.0, 10.0, 10.0);
+        let b = Transform::from_xyz(10.0, 10.0, 10.0)
+            .with_rotation(Quat::from_rotation_y(1.0))
+            .with_scale(Vec3::splat(3.0));
+        let lerped = torus.lerp_transform(&a, &b, 0.5);
+        assert!(lerped.translation.distance(Vec3::new(2.0, 10.0, 10.0)) < 1e-3, "{}", lerped.translation);
+        assert!(lerped.rotation.angle_between(Quat::from_rotation_y(0.5)) < 1e-4);
+        assert_eq!(lerped.scale, Vec3::splat(2.0));
+    }
+
+    #[test]
+    fn segments_hit_the_sphere_image_closest_to_them() {
+        let torus = Torus::default();
+        for (start, center) in pairs() {
+            // short enough that there's only the one image to hit
+            let delta = (center - start).normalize_or_zero() * 100.0;
+            let old = finite_cube_point_to_closest_visible_location(start + delta * 0.5, center);
+            for radius in [1.0, 50.0, 500.0] {
+                let t = torus.segment_sphere_intersection(start, delta, center, radius);
+                let expected = crate::math::segment_sphere_intersection(start, start + delta, old, radius);
+                assert_eq!(t.is_some(), expected.is_some(), "{start} by {delta} against {center}, radius {radius}");
+                if let (Some(t), Some(expected)) = (t, expected) {
+                    assert!((t - expected).abs() < 1e-3);
+                }
+            }
+        }
+        // a bullet leaving through one face hits a ship just inside the other
+        let t = torus.segment_sphere_intersection(Vec3::new(SIZE - 5.0, 0.0, 0.0), Vec3::X * 10.0, Vec3::new(2.0, 0.0, 0.0), 1.5);
+        assert_eq!(t, Some(0.55));
+    }
+
+    #[test]
+    fn rays_are_segments_measured_in_distance() {
+        let torus = Torus::default();
+        let origin = Vec3::new(10.0, 20.0, 30.0);
+        let center = Vec3::new(10.0, 20.0, SIZE - 70.0);
+        // backwards across the seam, 100 away from the far side of the sphere
+        let hit = torus.ray_sphere_intersection(origin, -Vec3::Z, 200.0, center, 10.0);
+        assert!(hit.is_some_and(|distance| (distance - 90.0).abs() < 1e-2), "{hit:?}");
+        assert_eq!(torus.ray_sphere_intersection(origin, -Vec3::Z, 50.0, center, 10.0), None);
+        assert_eq!(torus.ray_sphere_intersection(origin, Vec3::Z, 200.0, center, 10.0), None);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{arena::Arena, components::*, gameplay::*, input::*};
+use bevy::prelude::*;
+use bevy_ggrs::prelude::*;
+
+pub const MISSILE_SPEED: f32 = 120.0;
+/// Slower than ships can pitch, so hard turns can out-turn a missile
+const MISSILE_TURN_RATE: f32 = 80.0_f32.to_radians();
+const MISSILE_DAMAGE: f32 = 60.0;
+const MISSILE_LIFETIME_FRAMES: u32 = 8 * 60;
+/// Targets further off the nose than this can't be locked at launch
+pub const LOCK_CONE: f32 = 30.0_f32.to_radians();
+pub const LOCK_RANGE: f32 = 1500.0;
+/// Missiles lose whatever slips further off their nose than this
+const SEEKER_CONE: f32 = 45.0_f32.to_radians();
+/// Flares closer than this that the seeker can see lure missiles away
+const FLARE_LURE_RANGE: f32 = 400.0;
+/// Missiles that reach the flare they're chasing burst harmlessly
+const FLARE_BURST_RADIUS: f32 = 5.0;
+
+const FLARE_INTERVAL_FRAMES: u32 = 30;
+const FLARE_LIFETIME_FRAMES: u32 = 3 * 60;
+const FLARE_EJECT_SPEED: f32 = 15.0;
+/// How quickly flares lose the speed they were dropped with, per second
+const FLARE_DRAG: f32 = 1.0;
+
+fn player_input(
+    inputs: &Option<Res<PlayerInputs<Config>>>,
+    local_inputs: &Option<Res<LocalInputs<Config>>>,
+    handle: usize,
+) -> [u8; 3] {
+    if let Some(inputs) = inputs {
+        inputs[handle].0
+    } else if let Some(inputs) = local_inputs {
+        inputs.0[&handle]
+    } else {
+        [0; 3]
+    }
+}
+
+/// Angle between where `transform` points and `to_target`, which is relative to it
+fn angle_off_nose(transform: &Transform, to_target: Vec3) -> f32 {
+    let nose = transform.rotation.mul_vec3(Vec3::Z);
+    if to_target.length_squared() == 0.0 {
+        return 0.0;
+    }
+    nose.angle_between(to_target)
+}
+
+/// Launches a missile each time the missile button is pressed, locked onto
+/// the other ship closest to the launcher's nose if one is in the lock cone
+#[allow(clippy::too_many_arguments)]
+pub fn fire_missiles(
+    mut commands: Commands,
+    inputs: Option<Res<PlayerInputs<Config>>>,
+    local_inputs: Option<Res<LocalInputs<Config>>>,
+    mut players: Query<(&Transform, &Player, &mut MissileAmmo, &mut MissileReady)>,
+    targets: Query<(&Transform, &Player)>,
+    frame: Res<SimulationFrame>,
+    arena: Res<Arena>,
+    mut next_bullet_id: ResMut<NextBulletId>,
+) {
+    // launches take ids in handle order, the same on every peer
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(_, player, ..)| player.handle);
+    for (transform, player, ammo, ready) in &mut players {
+        let input = player_input(&inputs, &local_inputs, player.handle);
+        if !fire_missile(input) {
+            ready.0 = true;
+            continue;
+        }
+        if !ready.0 || ammo.0 == 0 {
+            continue;
+        }
+        ready.0 = false;
+        ammo.0 -= 1;
+
+        let mut target = MissileTarget::None;
+        let mut best_angle = LOCK_CONE;
+        for (target_transform, target_player) in &targets {
+            if target_player.handle == player.handle {
+                continue;
+            }
+            let to_target = arena.displacement(transform.translation, target_transform.translation);
+            if to_target.length() > LOCK_RANGE {
+                continue;
+            }
+            let angle = angle_off_nose(transform, to_target);
+            // ties go to the lower handle, whatever order the query is in
+            let better = angle < best_angle
+                || (angle == best_angle && matches!(target, MissileTarget::Ship(handle) if target_player.handle < handle));
+            if better {
+                best_angle = angle;
+                target = MissileTarget::Ship(target_player.handle);
+            }
+        }
+
+        let missile_transform = **transform * Transform::from_translation(Vec3::new(0.0, -1.5, 2.0));
+        commands
+            .spawn((
+                Missile { owner: player.handle, target },
+                next_bullet_id.take(),
+                BirthFrame(frame.0),
+                Damage(MISSILE_DAMAGE),
+                Transform {
+                    translation: arena.wrap(missile_transform.translation),
+                    ..missile_transform
+                },
+            ))
+            .add_rollback();
+    }
+}
+
+/// Steers missiles towards their target at a capped turn rate, and lets
+/// their seekers lose targets that slip out of view or be lured by flares
+#[allow(clippy::type_complexity)]
+pub fn guide_missiles(
+    mut commands: Commands,
+    mut missiles: Query<(Entity, &mut Transform, &mut Missile, &BirthFrame), (Without<Player>, Without<Flare>)>,
+    players: Query<(&Transform, &Player), (Without<Missile>, Without<Flare>)>,
+    flares: Query<(&Transform, &BulletId), (With<Flare>, Without<Missile>)>,
+    frame: Res<SimulationFrame>,
+    arena: Res<Arena>,
+    time: Res<Time>,
+) {
+    let mut flares: Vec<_> = flares.iter().collect();
+    flares.sort_by_key(|(_, flare_id)| **flare_id);
+
+    for (missile_entity, mut transform, mut missile, birth_frame) in &mut missiles {
+        if frame.0.wrapping_sub(birth_frame.0) >= MISSILE_LIFETIME_FRAMES {
+            commands.entity(missile_entity).despawn_recursive();
+            continue;
+        }
+
+        let target_pos = match missile.target {
+            MissileTarget::None => None,
+            MissileTarget::Ship(handle) => players
+                .iter()
+                .find(|(_, player)| player.handle == handle)
+                .map(|(target_transform, _)| target_transform.translation),
+            MissileTarget::Flare(flare_id) => flares
+                .iter()
+                .find(|(_, id)| **id == flare_id)
+                .map(|(flare_transform, _)| flare_transform.translation),
+        };
+        let mut to_target = target_pos.map(|pos| arena.displacement(transform.translation, pos));
+        if to_target.is_some_and(|offset| angle_off_nose(&transform, offset) > SEEKER_CONE) {
+            to_target = None;
+        }
+        if to_target.is_none() {
+            missile.target = MissileTarget::None;
+        }
+
+        // any flare in view that's closer than the target is hotter than it
+        if !matches!(missile.target, MissileTarget::Flare(_)) {
+            let lure_distance = to_target.map_or(FLARE_LURE_RANGE, |offset| offset.length().min(FLARE_LURE_RANGE));
+            for (flare_transform, flare_id) in &flares {
+                let to_flare = arena.displacement(transform.translation, flare_transform.translation);
+                if to_flare.length() < lure_distance && angle_off_nose(&transform, to_flare) <= SEEKER_CONE {
+                    missile.target = MissileTarget::Flare(**flare_id);
+                    to_target = Some(to_flare);
+                    break;
+                }
+            }
+        }
+
+        if let Some(to_target) = to_target {
+            if matches!(missile.target, MissileTarget::Flare(_)) && to_target.length() < FLARE_BURST_RADIUS {
+                commands.entity(missile_entity).despawn_recursive();
+                continue;
+            }
+            let nose = transform.rotation.mul_vec3(Vec3::Z);
+            let to_target = to_target.normalize_or_zero();
+            let turn = Quat::from_rotation_arc(nose, to_target);
+            let (axis, angle) = turn.to_axis_angle();
+            let max_turn = MISSILE_TURN_RATE * time.delta_seconds();
+            if angle > max_turn {
+                transform.rotation = Quat::from_axis_angle(axis, max_turn) * transform.rotation;
+            } else {
+                transform.rotation = turn * transform.rotation;
+            }
+        }
+
+        let delta = transform.rotation * (Vec3::Z * MISSILE_SPEED * time.delta_seconds());
+        transform.translation = arena.wrap(transform.translation + delta);
+    }
+}
+
+/// Holding throttle down at idle throttle drops a pair of flares behind the ship
+#[allow(clippy::too_many_arguments)]
+pub fn drop_flares(
+    mut commands: Commands,
+    inputs: Option<Res<PlayerInputs<Config>>>,
+    local_inputs: Option<Res<LocalInputs<Config>>>,
+    mut players: Query<(&Transform, &Velocity, &Throttle, &Player, &mut FlareAmmo, &mut FlareCooldown)>,
+    frame: Res<SimulationFrame>,
+    arena: Res<Arena>,
+    mut next_bullet_id: ResMut<NextBulletId>,
+) {
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(_, _, _, player, ..)| player.handle);
+    for (transform, velocity, throttle, player, ammo, cooldown) in &mut players {
+        let input = player_input(&inputs, &local_inputs, player.handle);
+        if throttle_input(input) >= 0.0 || throttle.0 > 0.0 {
+            continue;
+        }
+        if ammo.0 == 0 || frame.0 < cooldown.0 {
+            continue;
+        }
+        ammo.0 -= 1;
+        cooldown.0 = frame.0 + FLARE_INTERVAL_FRAMES;
+        for side in [-1.0, 1.0] {
+            let eject = transform.rotation * Vec3::new(side, 0.0, -1.0).normalize() * FLARE_EJECT_SPEED;
+            let position = transform.transform_point(Vec3::new(side * 2.0, 0.0, -3.0));
+            commands
+                .spawn((
+                    Flare,
+                    next_bullet_id.take(),
+                    BirthFrame(frame.0),
+                    Velocity(velocity.0 + eject),
+                    Transform::from_translation(arena.wrap(position)),
+                ))
+                .add_rollback();
+        }
+    }
+}
+
+pub fn move_flares(
+    mut commands: Commands,
+    mut flares: Query<(Entity, &mut Transform, &mut Velocity, &BirthFrame), With<Flare>>,
+    frame: Res<SimulationFrame>,
+    arena: Res<Arena>,
+    time: Res<Time>,
+) {
+    for (flare_entity, mut transform, mut velocity, birth_frame) in &mut flares {
+        if frame.0.wrapping_sub(birth_frame.0) >= FLARE_LIFETIME_FRAMES {
+            commands.entity(flare_entity).despawn_recursive();
+            continue;
+        }
+        velocity.0 *= (1.0 - FLARE_DRAG * time.delta_seconds()).max(0.0);
+        transform.translation = arena.wrap(transform.translation + velocity.0 * time.delta_seconds());
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{bots::*, components::*, config::GameConfig, spectator::*, game::{CustomizeMaterial, ModelAssets, ModelAssets2}, arena::Arena, pbr_material::CustomStandardMaterial};
+use bevy::{ecs::system::SystemParam, prelude::*, render::camera::Viewport, transform::TransformSystem, utils::HashMap, window::PrimaryWindow};
+use bevy_ggrs::LocalPlayers;
+use std::hash::Hash;
+
+/// Keeps the models, bullet meshes and camera in step with the rollback
+/// simulation. Runs after the simulation has settled for the frame, so
+/// nothing here is churned by rollbacks.
+pub struct PresentationPlugin;
+
+impl Plugin for PresentationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (
+                spawn_player_models.run_if(resource_exists::<ModelAssets>()),
+                sync_player_models.after(spawn_player_models),
+                sync_meshes::<Bullet, BulletId>.run_if(resource_exists::<ModelAssets2>()),
+                sync_meshes::<Asteroid, AsteroidId>.run_if(resource_exists::<ModelAssets2>()),
+                sync_meshes::<Missile, BulletId>.run_if(resource_exists::<ModelAssets2>()),
+                sync_meshes::<Flare, BulletId>.run_if(resource_exists::<ModelAssets2>()),
+                camera_follow,
+                move_skybox_with_camera.after(camera_follow),
+                fit_viewports,
+            )
+                .before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// Who the cameras watch: the local pilots, or whoever a spectator picked
+#[derive(SystemParam)]
+pub struct Viewpoint<'w> {
+    game_config: Res<'w, GameConfig>,
+    local_players: Option<Res<'w, LocalPlayers>>,
+    bots: Option<Res<'w, Bots>>,
+    spectator: Option<Res<'w, SpectatorCamera>>,
+}
+
+impl Viewpoint<'_> {
+    /// Handles of the ships the cameras follow, the first [`PilotCamera`]'s
+    /// first, and none while a spectator flies the free camera
+    pub fn handles(&self) -> Vec<usize> {
+        match self.spectator.as_deref() {
+            Some(SpectatorCamera { view: SpectatorView::Follow(handle), .. }) => vec![*handle],
+            Some(_) => Vec::new(),
+            None => {
+                let mut handles = pilot_handles(&self.local_players, &self.bots);
+                handles.truncate(self.game_config.local_players);
+                handles
+            }
+        }
+    }
+
+    /// Where the watched pilots or the free camera are, which decides which
+    /// images of the wrapped cube everything else is drawn at
+    fn observer_positions(&self, players: impl Iterator<Item = (Player, Vec3)>) -> Vec<Vec3> {
+        if let Some(SpectatorCamera { view: SpectatorView::Free, position }) = self.spectator.as_deref() {
+            return vec![*position];
+        }
+        let watched = self.handles();
+        let mut observers: Vec<(usize, Vec3)> = players
+            .filter(|(player, _)| watched.contains(&player.handle))
+            .map(|(player, translation)| (player.handle, translation))
+            .collect();
+        if observers.is_empty() {
+            return vec![Vec3::ZERO];
+        }
+        observers.sort_by_key(|(handle, _)| *handle);
+        observers.into_iter().map(|(_, translation)| translation).collect()
+    }
+}
+
+/// Images of a wrapped arena further away than this many arena sizes aren't
+/// drawn, apart from the closest one
+const VIEW_DISTANCE: f32 = 0.75;
+
+/// Where to draw something at `translation`: every image within view of any
+/// observer, so things approaching across the seam can be seen from both sides
+fn visible_images(arena: Arena, observers: &[Vec3], translation: Vec3) -> Vec<Vec3> {
+    let mut images: Vec<Vec3> = Vec::new();
+    for observer in observers {
+        for image in arena.images_within(*observer, translation, VIEW_DISTANCE * arena.size) {
+            // images are whole arena sizes apart, so anything closer is the same one
+            if images.iter().all(|seen| seen.distance_squared(image) > 1.0) {
+                images.push(image);
+            }
+        }
+    }
+    images
+}
+
+/// Spawns 3D models for every player handle and ship class it flies until
+/// there's one for each of its visible images. Models are never despawned,
+/// spare ones are hidden, so scenes don't have to be loaded again when images
+/// come in and out of view.
+fn spawn_player_models(
+    mut commands: Commands,
+    models: Res<ModelAssets>,
+    viewpoint: Viewpoint,
+    arena: Res<Arena>,
+    players: Query<(&Transform, &Player, &ShipClass), Without<FollowPlayer>>,
+    follow_players: Query<&FollowPlayer>,
+) {
+    let observers = viewpoint.observer_positions(
+        players.iter().map(|(transform, player, _)| (*player, transform.translation)),
+    );
+    let mut num_models: HashMap<(usize, usize), usize> = HashMap::new();
+    for follow_player in &follow_players {
+        *num_models.entry((follow_player.target_player_handle, follow_player.ship_class)).or_default() += 1;
+    }
+    for (transform, player, class) in &players {
+        let num_images = visible_images(*arena, &observers, transform.translation).len();
+        let num_models = num_models.entry((player.handle, class.0)).or_default();
+        for ghost in *num_models..num_images {
+            commands
+                .spawn((
+                    FollowPlayer { target_player_handle: player.handle, ship_class: class.0, ghost, },
+                    SceneBundle {
+                        scene: models.ships[class.0].clone(),
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    CustomizeMaterial,
+                ));
+        }
+        *num_models = (*num_models).max(num_images);
+    }
+}
+
+fn sync_player_models(
+    viewpoint: Viewpoint,
+    arena: Res<Arena>,
+    players: Query<(&Transform, &Player, &ShipClass), Without<FollowPlayer>>,
+    mut follow_players: Query<(&mut Transform, &mut Visibility, &FollowPlayer)>,
+) {
+    let observers = viewpoint.observer_positions(
+        players.iter().map(|(transform, player, _)| (*player, transform.translation)),
+    );
+    let player_images: HashMap<(usize, usize), (Transform, Vec<Vec3>)> = players
+        .iter()
+        .map(|(transform, player, class)| ((player.handle, class.0), (*transform, visible_images(*arena, &observers, transform.translation))))
+        .collect();
+    for (mut transform, mut visibility, follow_player) in &mut follow_players {
+        let image = player_images
+            .get(&(follow_player.target_player_handle, follow_player.ship_class))
+            .and_then(|(player_transform, images)| Some((player_transform, *images.get(follow_player.ghost)?)));
+        let Some((player_transform, translation)) = image else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *transform = *player_transform;
+        transform.translation = translation;
+        *visibility = Visibility::Visible;
+    }
+}
+
+/// Simulated things drawn with a mesh at each of their visible images
+trait Meshed: Component {
+    fn mesh(models: &ModelAssets2) -> (Handle<Mesh>, Handle<CustomStandardMaterial>);
+
+    /// Where the mesh goes for the image at `translation`
+    fn mesh_transform(&self, transform: &Transform, translation: Vec3) -> Transform {
+        Transform { translation, ..*transform }
+    }
+}
+
+impl Meshed for Bullet {
+    fn mesh(models: &ModelAssets2) -> (Handle<Mesh>, Handle<CustomStandardMaterial>) {
+        (models.bullet_mesh.clone(), models.bullet_material.clone())
+    }
+}
+
+impl Meshed for Missile {
+    fn mesh(models: &ModelAssets2) -> (Handle<Mesh>, Handle<CustomStandardMaterial>) {
+        (models.missile_mesh.clone(), models.missile_material.clone())
+    }
+}
+
+impl Meshed for Flare {
+    fn mesh(models: &ModelAssets2) -> (Handle<Mesh>, Handle<CustomStandardMaterial>) {
+        (models.flare_mesh.clone(), models.flare_material.clone())
+    }
+
+    fn mesh_transform(&self, _: &Transform, translation: Vec3) -> Transform {
+        Transform::from_translation(translation)
+    }
+}
+
+impl Meshed for Asteroid {
+    fn mesh(models: &ModelAssets2) -> (Handle<Mesh>, Handle<CustomStandardMaterial>) {
+        (models.asteroid_mesh.clone(), models.asteroid_material.clone())
+    }
+
+    fn mesh_transform(&self, _: &Transform, translation: Vec3) -> Transform {
+        Transform::from_translation(translation).with_scale(Vec3::splat(self.radius))
+    }
+}
+
+/// Gives every visible image of every `Marker` a mesh, matched up by its
+/// `Id` so a mesh keeps following the same thing however rollbacks reorder
+/// or respawn them
+#[allow(clippy::type_complexity)]
+fn sync_meshes<Marker: Meshed, Id: Component + Copy + Eq + Hash>(
+    mut commands: Commands,
+    models: Res<ModelAssets2>,
+    viewpoint: Viewpoint,
+    arena: Res<Arena>,
+    players: Query<(&Transform, &Player), Without<FollowMesh<Marker, Id>>>,
+    things: Query<(&Transform, &Id, &Marker), Without<FollowMesh<Marker, Id>>>,
+    mut meshes: Query<(Entity, &mut Transform, &FollowMesh<Marker, Id>), Without<Marker>>,
+) {
+    let observers = viewpoint.observer_positions(
+        players.iter().map(|(transform, player)| (*player, transform.translation)),
+    );
+    let mut transforms: HashMap<(Id, usize), Transform> = HashMap::new();
+    for (transform, id, marker) in &things {
+        for (ghost, translation) in visible_images(*arena, &observers, transform.translation).into_iter().enumerate() {
+            transforms.insert((*id, ghost), marker.mesh_transform(transform, translation));
+        }
+    }
+    for (mesh_entity, mut mesh_transform, follow) in &mut meshes {
+        let Some(transform) = transforms.remove(&(follow.id, follow.ghost)) else {
+            commands.entity(mesh_entity).despawn_recursive();
+            continue;
+        };
+        *mesh_transform = transform;
+    }
+    let (mesh, material) = Marker::mesh(&models);
+    for ((id, ghost), transform) in transforms {
+        commands.spawn((
+            FollowMesh::<Marker, Id>::new(id, ghost),
+            MaterialMeshBundle::<CustomStandardMaterial> {
+                transform,
+                mesh: mesh.clone(),
+                material: material.clone(),
+                ..default()
+            },
+        ));
+    }
+}
+
+fn camera_follow(
+    viewpoint: Viewpoint,
+    players: Query<(&Player, &Transform)>,
+    mut cameras: Query<(&mut Transform, &PilotCamera), Without<Player>>,
+    arena: Res<Arena>,
+    time: Res<Time>,
+) {
+    let watched = viewpoint.handles();
+    for (mut transform, pilot_camera) in &mut cameras {
+        let Some(handle) = watched.get(pilot_camera.0) else {
+            continue;
+        };
+        for (player, player_transform) in &players {
+            if player.handle != *handle {
+                continue;
+            }
+            let target = Transform {
+                translation: player_transform.transform_point(Vec3::new(0.0, 1.5, -10.0)),
+                rotation: player_transform.rotation * Quat::from_rotation_y(std::f32::consts::PI),
+                scale: transform.scale,
+            };
+            // chases the ship the short way round when it crosses the seam
+            *transform = arena.lerp_transform(&transform, &target, (8.0f32 * time.delta_seconds()).min(1.0));
+            // everything is drawn around the ship, so stay by it rather than in the cube
+            transform.translation = arena.closest_image(player_transform.translation, transform.translation);
+        }
+    }
+}
+
+/// Splits the window between the pilot cameras, side by side
+fn fit_viewports(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(&mut Camera, &PilotCamera)>,
+) {
+    let screens = cameras.iter().count() as u32;
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let size = UVec2::new(window.resolution.physical_width() / screens.max(1), window.resolution.physical_height());
+    if screens < 2 || size.x == 0 || size.y == 0 {
+        return;
+    }
+    for (mut camera, pilot_camera) in &mut cameras {
+        let position = UVec2::new(pilot_camera.0 as u32 * size.x, 0);
+        let fits = camera
+            .viewport
+            .as_ref()
+            .is_some_and(|viewport| viewport.physical_position == position && viewport.physical_size == size);
+        if !fits {
+            camera.viewport = Some(Viewport {
+                physical_position: position,
+                physical_size: size,
+                ..default()
+            });
+        }
+    }
+}
+
+fn move_skybox_with_camera(
+    camera: Query<&Transform, (With<PilotCamera>,Without<Skybox>)>,
+    mut skybox: Query<&mut Transform,With<Skybox>>,
+) {
+    for mut skybox_transform in &mut skybox {
+        for camera_transform in &camera {
+            skybox_transform.translation = camera_transform.translation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::ArenaShape;
+
+    fn sorted(mut images: Vec<Vec3>) -> Vec<Vec3> {
+        images.sort_by(|a, b| a.to_array().partial_cmp(&b.to_array()).unwrap());
+        images
+    }
+
+    #[test]
+    fn things_near_a_face_are_also_drawn_across_it() {
+        let arena = Arena::new(ArenaShape::WrappedCube, 1000.0);
+        assert_eq!(visible_images(arena, &[Vec3::ZERO], Vec3::ZERO), vec![Vec3::ZERO]);
+        assert_eq!(
+            sorted(visible_images(arena, &[Vec3::ZERO], Vec3::new(480.0, 0.0, 0.0))),
+            vec![Vec3::new(-520.0, 0.0, 0.0), Vec3::new(480.0, 0.0, 0.0)],
+        );
+        // near an edge, across both faces and the edge itself
+        assert_eq!(visible_images(arena, &[Vec3::ZERO], Vec3::new(480.0, 480.0, 0.0)).len(), 4);
+    }
+
+    #[test]
+    fn images_seen_by_several_observers_are_drawn_once() {
+        let arena = Arena::new(ArenaShape::WrappedCube, 1000.0);
+        let observers = [Vec3::new(-100.0, 0.0, 0.0), Vec3::new(100.0, 0.0, 0.0)];
+        assert_eq!(
+            sorted(visible_images(arena, &observers, Vec3::new(480.0, 0.0, 0.0))),
+            vec![Vec3::new(-520.0, 0.0, 0.0), Vec3::new(480.0, 0.0, 0.0)],
+        );
+    }
+
+    #[test]
+    fn arenas_that_do_not_wrap_draw_one_image() {
+        let arena = Arena::new(ArenaShape::Box, 1000.0);
+        let p = Vec3::new(480.0, 480.0, 0.0);
+        assert_eq!(visible_images(arena, &[Vec3::ZERO], p), vec![p]);
+    }
+}
@@ -1,9 +1,12 @@
 use bevy::{app::{Plugin, Startup, Update}, asset::AssetServer, ecs::{entity::Entity, system::{Commands, Query, Res}}, hierarchy::BuildChildren, math::{Vec2, Vec3}, prelude::default, render::color::Color, transform::components::Transform, ui::{node_bundles::{ImageBundle, NodeBundle}, BackgroundColor, PositionType, Style, UiImage, Val}};
 use bevy::ecs::component::Component;
-use bevy_ggrs::LocalPlayers;
+use bevy::ecs::query::With;
 use bevy::prelude::DespawnRecursiveExt;
 
-use crate::components::Player;
+use crate::arena::Arena;
+use crate::components::{Asteroid, Player};
+use crate::config::GameConfig;
+use crate::presentation::Viewpoint;
 
 pub struct RadarPlugin;
 
@@ -15,112 +18,125 @@ impl Plugin for RadarPlugin {
     }
 }
 
+/// Asteroids further away than this are left off the radar
+const ASTEROID_RANGE: f32 = 600.0;
+
+/// Radar of the pilot at this index among the pilots at this machine, in the
+/// corner of its part of the window
 #[derive(Component)]
-struct Radar;
+struct Radar {
+    pilot: usize,
+}
 
 #[derive(Component)]
 struct Blip {
+    pub pilot: usize,
     pub index: usize,
 }
 
 fn setup_radar_ui(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    game_config: Res<GameConfig>,
 ) {
-    commands
-        .spawn((
-            Radar,
-            ImageBundle {
-                style: Style {
-                    width: Val::Px(150.0),
-                    height: Val::Px(150.0),
-                    position_type: PositionType::Absolute,
-                    right: Val::Percent(5.0),
-                    top: Val::Percent(5.0),
-                    ..default()
-                },
-                image: UiImage {
-                    texture: asset_server.load("radar.png"),
+    let screens = game_config.local_players;
+    for pilot in 0..screens {
+        commands
+            .spawn((
+                Radar { pilot },
+                ImageBundle {
+                    style: Style {
+                        width: Val::Px(150.0),
+                        height: Val::Px(150.0),
+                        position_type: PositionType::Absolute,
+                        right: Val::Percent((5.0 + 100.0 * (screens - 1 - pilot) as f32) / screens as f32),
+                        top: Val::Percent(5.0),
+                        ..default()
+                    },
+                    image: UiImage {
+                        texture: asset_server.load("radar.png"),
+                        ..default()
+                    },
                     ..default()
                 },
-                ..default()
-            },
-        ));
+            ));
+    }
 }
 
 fn update_radar_ui(
     mut commands: Commands,
-    local_players: Option<Res<LocalPlayers>>,
+    viewpoint: Viewpoint,
+    arena: Res<Arena>,
     players: Query<(&Transform, &Player)>,
+    asteroids: Query<&Transform, With<Asteroid>>,
     radar: Query<(Entity,&Radar)>,
-    mut blips: Query<(Entity,&Blip,&mut Style)>,
+    mut blips: Query<(Entity,&Blip,&mut Style,&mut BackgroundColor)>,
 ) {
-    let Some(local_players) = local_players else { return; };
-    let mut index: usize = 0;
-    let mut local_transform: Transform = Transform::IDENTITY;
-    let mut local_player_found = false;
-    for local_player in &local_players.0 {
-        for (transform, player) in &players {
-            if player.handle == *local_player {
-                local_transform = *transform;
-                local_player_found = true;
-                break;
+    let pilots = viewpoint.handles();
+    for (radar_entity, radar) in &radar {
+        let mut index: usize = 0;
+        let local_player = pilots.get(radar.pilot).and_then(|handle| players.iter().find(|(_, player)| player.handle == *handle));
+        let Some((local_transform, local_player)) = local_player else {
+            for (blip_entity, blip, ..) in &blips {
+                if blip.pilot == radar.pilot {
+                    commands.entity(blip_entity).despawn_recursive();
+                }
             }
-        }
-        if local_player_found {
-            break;
-        }
-    }
-    if !local_player_found {
-        for (blip_entity, _, _) in &blips {
-            commands.entity(blip_entity).despawn_recursive();
-        }
-        return;
-    }
-    for (transform, player) in &players {
-        if local_players.0.contains(&player.handle) {
             continue;
-        }
+        };
         let p1 = local_transform.translation;
-        let p2 = crate::math::finite_cube_point_to_closest_visible_location(p1, transform.translation);
-        let d1 = p2 - p1;
-        let mut radius = 75.0 * d1.normalize().dot(local_transform.rotation.mul_vec3(Vec3::Z)).acos().abs() / std::f32::consts::PI;
-        if !radius.is_finite() {
-            radius = 0.0;
-        }
-        let angle: f32;
-        if radius >= 74.9 {
-            angle = 0.0;
-        } else if radius >= 1.0 {
-            let d2 = Vec2::new(
-                local_transform.rotation.mul_vec3(Vec3::X).dot(d1),
-                local_transform.rotation.mul_vec3(Vec3::Y).dot(d1),
-            );
-            angle = std::f32::consts::PI - d2.y.atan2(d2.x);
-        } else {
-            angle = 0.0;
-        }
-        let blip_pos = Vec2::new(
-            75.0 + angle.cos() * radius,
-            75.0 - angle.sin() * radius,
-        );
-        let mut has_blip: bool = false;
-        for (_, blip, mut blip_style) in &mut blips {
-            if blip.index != index {
+        let mut targets: Vec<(Vec3, Color)> = Vec::new();
+        // bots and the other split-screen pilot show up like anyone else
+        for (transform, player) in &players {
+            if player.handle == local_player.handle {
                 continue;
             }
-            has_blip = true;
-            blip_style.left = Val::Px(blip_pos.x - 5.0);
-            blip_style.top = Val::Px(blip_pos.y - 5.0);
-            break;
+            targets.push((arena.displacement(p1, transform.translation), Color::RED));
         }
-        if !has_blip {
-            for (radar_entity, _) in &radar {
+        for transform in &asteroids {
+            let d1 = arena.displacement(p1, transform.translation);
+            if d1.length() <= ASTEROID_RANGE {
+                targets.push((d1, Color::GRAY));
+            }
+        }
+        for (d1, color) in targets {
+            let mut radius = 75.0 * d1.normalize().dot(local_transform.rotation.mul_vec3(Vec3::Z)).acos().abs() / std::f32::consts::PI;
+            if !radius.is_finite() {
+                radius = 0.0;
+            }
+            let angle: f32;
+            if radius >= 74.9 {
+                angle = 0.0;
+            } else if radius >= 1.0 {
+                let d2 = Vec2::new(
+                    local_transform.rotation.mul_vec3(Vec3::X).dot(d1),
+                    local_transform.rotation.mul_vec3(Vec3::Y).dot(d1),
+                );
+                angle = std::f32::consts::PI - d2.y.atan2(d2.x);
+            } else {
+                angle = 0.0;
+            }
+            let blip_pos = Vec2::new(
+                75.0 + angle.cos() * radius,
+                75.0 - angle.sin() * radius,
+            );
+            let mut has_blip: bool = false;
+            for (_, blip, mut blip_style, mut blip_color) in &mut blips {
+                if blip.pilot != radar.pilot || blip.index != index {
+                    continue;
+                }
+                has_blip = true;
+                blip_style.left = Val::Px(blip_pos.x - 5.0);
+                blip_style.top = Val::Px(blip_pos.y - 5.0);
+                blip_color.0 = color;
+                break;
+            }
+            if !has_blip {
                 commands
                     .entity(radar_entity)
                     .with_children(|parent| {
                         parent.spawn((
-                            Blip { index: index, },
+                            Blip { pilot: radar.pilot, index: index, },
                             NodeBundle {
                                 style: Style {
                                     width: Val::Px(10.0),
@@ -130,19 +146,18 @@ fn update_radar_ui(
                                     top: Val::Px(blip_pos.y - 5.0),
                                     ..default()
                                 },
-                                background_color: BackgroundColor(Color::RED),
+                                background_color: BackgroundColor(color),
                                 ..default()
                             }
                         ));
                     });
-                break;
             }
+            index += 1;
         }
-        index += 1;
-    }
-    for (blip_entity, blip, _) in &blips {
-        if blip.index >= index {
-            commands.entity(blip_entity).despawn_recursive();
+        for (blip_entity, blip, ..) in &blips {
+            if blip.pilot == radar.pilot && blip.index >= index {
+                commands.entity(blip_entity).despawn_recursive();
+            }
         }
     }
 }
@@ -0,0 +1,482 @@
+use crate::{arena::{Arena, ArenaShape}, args::Args, config::MAX_PLAYERS, flight::FlightAssist, gameplay::*};
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ggrs::{ggrs::InputStatus, prelude::*, LocalInputs, LocalPlayers};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+const REPLAY_MAGIC: [u8; 4] = *b"FSRP";
+const REPLAY_VERSION: u16 = 7;
+/// Longer ship names than this are taken for a corrupt file rather than
+/// allocated for
+const MAX_SHIP_NAME_LEN: usize = 64;
+
+/// Records confirmed inputs to a replay file and plays replay files back
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(
+                ReadInputs,
+                read_replay_inputs.run_if(resource_exists::<ReplayPlayback>()),
+            )
+            .add_systems(
+                GgrsSchedule,
+                record_simulated_inputs
+                    .run_if(resource_exists::<ReplayRecorder>())
+                    .before(tick_simulation_frame),
+            )
+            .add_systems(
+                Update,
+                record_confirmed_frames
+                    .run_if(resource_exists::<ReplayRecorder>())
+                    .run_if(resource_exists::<Session<Config>>()),
+            );
+    }
+}
+
+/// Session settings and initial layout a replay was recorded with. The
+/// spawn points follow from the settings, but are stored anyway so a replay
+/// from a build that spawns ships elsewhere is turned away instead of
+/// playing back a different match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayHeader {
+    pub num_players: usize,
+    pub input_delay: usize,
+    pub asteroid_seed: u64,
+    pub flight_assist: FlightAssist,
+    pub arena: Arena,
+    /// Name of the ship each player handle flew
+    pub ships: Vec<String>,
+    /// Where each player handle started the first round
+    pub spawns: Vec<Transform>,
+}
+
+impl ReplayHeader {
+    pub fn new(
+        num_players: usize,
+        input_delay: usize,
+        asteroid_seed: u64,
+        flight_assist: FlightAssist,
+        arena: Arena,
+        ships: Vec<String>,
+    ) -> Self {
+        ReplayHeader {
+            num_players,
+            input_delay,
+            asteroid_seed,
+            flight_assist,
+            arena,
+            ships,
+            spawns: spawn_layout(num_players, arena),
+        }
+    }
+
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&REPLAY_MAGIC)?;
+        out.write_all(&REPLAY_VERSION.to_le_bytes())?;
+        out.write_all(&(self.num_players as u32).to_le_bytes())?;
+        out.write_all(&(self.input_delay as u32).to_le_bytes())?;
+        out.write_all(&self.asteroid_seed.to_le_bytes())?;
+        let flight_assist: u8 = match self.flight_assist {
+            FlightAssist::Arcade => 0,
+            FlightAssist::Newtonian => 1,
+        };
+        out.write_all(&[flight_assist])?;
+        let arena_shape: u8 = match self.arena.shape {
+            ArenaShape::WrappedCube => 0,
+            ArenaShape::Box => 1,
+            ArenaShape::Sphere => 2,
+        };
+        out.write_all(&[arena_shape])?;
+        out.write_all(&self.arena.size.to_le_bytes())?;
+        for ship in &self.ships {
+            out.write_all(&(ship.len() as u32).to_le_bytes())?;
+            out.write_all(ship.as_bytes())?;
+        }
+        for spawn in &self.spawns {
+            let t = spawn.translation;
+            let r = spawn.rotation;
+            for x in [t.x, t.y, t.z, r.x, r.y, r.z, r.w] {
+                out.write_all(&x.to_bits().to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if magic != REPLAY_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a replay file"));
+        }
+        let mut version = [0u8; 2];
+        input.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != REPLAY_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported replay version {version}, expected {REPLAY_VERSION}"),
+            ));
+        }
+        let num_players = read_u32(input)? as usize;
+        if num_players > MAX_PLAYERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{num_players} players, no match has more than {MAX_PLAYERS}"),
+            ));
+        }
+        let input_delay = read_u32(input)? as usize;
+        let mut asteroid_seed = [0u8; 8];
+        input.read_exact(&mut asteroid_seed)?;
+        let asteroid_seed = u64::from_le_bytes(asteroid_seed);
+        let mut flight_assist = [0u8; 1];
+        input.read_exact(&mut flight_assist)?;
+        let flight_assist = match flight_assist[0] {
+            0 => FlightAssist::Arcade,
+            1 => FlightAssist::Newtonian,
+            other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown flight assist {other}"))),
+        };
+        let mut arena_shape = [0u8; 1];
+        input.read_exact(&mut arena_shape)?;
+        let arena_shape = match arena_shape[0] {
+            0 => ArenaShape::WrappedCube,
+            1 => ArenaShape::Box,
+            2 => ArenaShape::Sphere,
+            other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown arena shape {other}"))),
+        };
+        let arena_size = f32::from_bits(read_u32(input)?);
+        if !(arena_size.is_finite() && arena_size > 0.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad arena size {arena_size}")));
+        }
+        let mut ships = Vec::with_capacity(num_players);
+        for _ in 0..num_players {
+            let len = read_u32(input)? as usize;
+            if len > MAX_SHIP_NAME_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ship name of {len} bytes, longer than {MAX_SHIP_NAME_LEN}"),
+                ));
+            }
+            let mut ship = vec![0u8; len];
+            input.read_exact(&mut ship)?;
+            let ship = String::from_utf8(ship).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            ships.push(ship);
+        }
+        let arena = Arena::new(arena_shape, arena_size);
+        let mut spawns = Vec::with_capacity(num_players);
+        for _ in 0..num_players {
+            let mut x = [0f32; 7];
+            for x in &mut x {
+                *x = f32::from_bits(read_u32(input)?);
+            }
+            spawns.push(Transform {
+                translation: Vec3::new(x[0], x[1], x[2]),
+                rotation: Quat::from_xyzw(x[3], x[4], x[5], x[6]),
+                ..default()
+            });
+        }
+        if spawns != spawn_layout(num_players, arena) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "replay was recorded with a different spawn layout",
+            ));
+        }
+        Ok(ReplayHeader {
+            num_players,
+            input_delay,
+            asteroid_seed,
+            flight_assist,
+            arena,
+            ships,
+            spawns,
+        })
+    }
+}
+
+/// Where `spawn_players` puts each player handle
+fn spawn_layout(num_players: usize, arena: Arena) -> Vec<Transform> {
+    (0..num_players)
+        .map(|handle| spawn_transform(handle, num_players, arena))
+        .collect()
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Appends every confirmed frame's inputs to a replay file.
+///
+/// A P2P session simulates most frames only once, on predicted inputs,
+/// and only simulates them again if a prediction turns out wrong. So the
+/// inputs of each frame are kept from its latest simulation until the
+/// session has confirmed it, and written out then.
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    file: File,
+    num_players: usize,
+    /// Every frame before this one has been written
+    next_frame: u32,
+    /// Inputs of the frames simulated but not written yet, in frame order
+    pending: VecDeque<(u32, Vec<[u8; 3]>)>,
+}
+
+impl ReplayRecorder {
+    pub fn create(path: impl AsRef<Path>, header: &ReplayHeader) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        header.write(&mut file)?;
+        Ok(ReplayRecorder {
+            file,
+            num_players: header.num_players,
+            next_frame: 0,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Keeps the inputs `frame` was just simulated with, in place of those
+    /// of any earlier simulation of it or the frames after it. A frame
+    /// simulated on nothing but confirmed inputs is confirmed itself, and so
+    /// is every frame before it.
+    fn simulated(&mut self, frame: u32, inputs: &[([u8; 3], InputStatus)]) -> io::Result<()> {
+        if frame < self.next_frame {
+            return Ok(()); // already written
+        }
+        self.pending.retain(|(pending, _)| *pending < frame);
+        self.pending.push_back((frame, inputs.iter().map(|(input, _)| *input).collect()));
+        if inputs.iter().all(|(_, status)| *status != InputStatus::Predicted) {
+            self.confirm(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out every kept frame up to and including `confirmed`
+    fn confirm(&mut self, confirmed: u32) -> io::Result<()> {
+        while self.pending.front().is_some_and(|(frame, _)| *frame <= confirmed) {
+            let (frame, inputs) = self.pending.pop_front().expect("checked above");
+            let mut bytes = Vec::with_capacity(4 + 3 * self.num_players);
+            bytes.extend_from_slice(&frame.to_le_bytes());
+            for input in &inputs {
+                bytes.extend_from_slice(input);
+            }
+            self.file.write_all(&bytes)?;
+            self.next_frame = frame + 1;
+        }
+        Ok(())
+    }
+}
+
+/// Starts recording if `--record` was given, logging instead of failing
+/// the match when the file can't be written
+#[allow(clippy::too_many_arguments)]
+pub fn start_recording(
+    commands: &mut Commands,
+    args: &Args,
+    num_players: usize,
+    input_delay: usize,
+    asteroid_seed: u64,
+    flight_assist: FlightAssist,
+    arena: Arena,
+    ships: &[String],
+) {
+    let Some(path) = &args.record else { return; };
+    let header = ReplayHeader::new(num_players, input_delay, asteroid_seed, flight_assist, arena, ships.to_vec());
+    match ReplayRecorder::create(path, &header) {
+        Ok(recorder) => {
+            info!("recording replay to {path}");
+            commands.insert_resource(recorder);
+        }
+        Err(e) => error!("failed to create replay file {path}: {e}"),
+    }
+}
+
+/// Inputs loaded from a replay file, fed back in as local inputs
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    pub header: ReplayHeader,
+    frames: Vec<Vec<[u8; 3]>>,
+    cursor: usize,
+}
+
+impl ReplayPlayback {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let header = ReplayHeader::read(&mut input)?;
+        let mut frames = Vec::new();
+        loop {
+            let frame = match read_u32(&mut input) {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+            if frame as usize != frames.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected frame {}, found frame {frame}", frames.len()),
+                ));
+            }
+            let mut inputs = vec![[0u8; 3]; header.num_players];
+            for input_bytes in &mut inputs {
+                input.read_exact(input_bytes)?;
+            }
+            frames.push(inputs);
+        }
+        Ok(ReplayPlayback {
+            header,
+            frames,
+            cursor: 0,
+        })
+    }
+}
+
+fn record_simulated_inputs(
+    mut recorder: ResMut<ReplayRecorder>,
+    frame: Res<SimulationFrame>,
+    inputs: Res<PlayerInputs<Config>>,
+) {
+    if let Err(e) = recorder.simulated(frame.0, &inputs) {
+        error!("failed to write replay frame {}: {e}", frame.0);
+    }
+}
+
+/// Writes out the frames a P2P session has confirmed since, which the
+/// simulation never sees confirmed when it predicted them right
+fn record_confirmed_frames(mut recorder: ResMut<ReplayRecorder>, session: Res<Session<Config>>) {
+    let Session::P2P(session) = session.as_ref() else {
+        return; // every input of the other sessions is confirmed
+    };
+    let Ok(confirmed) = u32::try_from(session.confirmed_frame()) else {
+        return; // nothing confirmed yet
+    };
+    if let Err(e) = recorder.confirm(confirmed) {
+        error!("failed to write replay frame {confirmed}: {e}");
+    }
+}
+
+fn read_replay_inputs(
+    mut commands: Commands,
+    mut playback: ResMut<ReplayPlayback>,
+    local_players: Res<LocalPlayers>,
+) {
+    let cursor = playback.cursor;
+    if cursor == playback.frames.len() {
+        info!("replay finished after {cursor} frames");
+    }
+    let frame = playback.frames.get(cursor);
+    let mut local_inputs = HashMap::new();
+    for handle in &local_players.0 {
+        let input = frame
+            .and_then(|frame| frame.get(*handle))
+            .copied()
+            .unwrap_or([0u8; 3]);
+        local_inputs.insert(*handle, input);
+    }
+    commands.insert_resource(LocalInputs::<Config>(local_inputs));
+    playback.cursor += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("flying_shooter_{name}_{}.fsrp", std::process::id()))
+    }
+
+    fn header() -> ReplayHeader {
+        ReplayHeader::new(2, 2, 7, FlightAssist::Newtonian, Arena::new(ArenaShape::Sphere, 2000.0), vec!["X-Wing".to_string(), "Interceptor".to_string()])
+    }
+
+    #[test]
+    fn correctly_predicted_frames_are_written_once_confirmed() {
+        let path = temp_path("predicted");
+        let mut recorder = ReplayRecorder::create(&path, &header()).unwrap();
+        let len = || std::fs::metadata(&path).unwrap().len();
+        let header_len = len();
+        let frame_len = 4 + 3 * 2;
+        let predicted = |x: u8| [([x, 0, 0], InputStatus::Confirmed), ([0, 0, 0], InputStatus::Predicted)];
+
+        // the remote input is predicted right, so these are never simulated again
+        for frame in 0..3 {
+            recorder.simulated(frame, &predicted(frame as u8)).unwrap();
+        }
+        assert_eq!(len(), header_len);
+        recorder.confirm(1).unwrap();
+        assert_eq!(len(), header_len + 2 * frame_len);
+        recorder.simulated(3, &predicted(3)).unwrap();
+        recorder.confirm(3).unwrap();
+        assert_eq!(len(), header_len + 4 * frame_len);
+        // nothing to wait for when every input is in
+        recorder.simulated(4, &[([4, 0, 0], InputStatus::Confirmed), ([1, 0, 0], InputStatus::Confirmed)]).unwrap();
+        assert_eq!(len(), header_len + 5 * frame_len);
+        drop(recorder);
+
+        let playback = ReplayPlayback::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(playback.header, header());
+        assert_eq!(playback.frames.len(), 5);
+        assert_eq!(playback.frames[2], vec![[2, 0, 0], [0, 0, 0]]);
+        assert_eq!(playback.frames[4], vec![[4, 0, 0], [1, 0, 0]]);
+    }
+
+    #[test]
+    fn mispredicted_frames_are_written_as_resimulated() {
+        let path = temp_path("mispredicted");
+        let mut recorder = ReplayRecorder::create(&path, &header()).unwrap();
+        for frame in 0..3 {
+            recorder.simulated(frame, &[([0, 0, 0], InputStatus::Confirmed), ([0, 0, 0], InputStatus::Predicted)]).unwrap();
+        }
+        // the remote pilot fired from frame 1 on, so the session rolls back
+        recorder.simulated(0, &[([0, 0, 0], InputStatus::Confirmed), ([0, 0, 0], InputStatus::Confirmed)]).unwrap();
+        for frame in 1..3 {
+            recorder.simulated(frame, &[([0, 0, 0], InputStatus::Confirmed), ([16, 0, 0], InputStatus::Confirmed)]).unwrap();
+        }
+        recorder.confirm(2).unwrap();
+        drop(recorder);
+
+        let playback = ReplayPlayback::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(playback.frames, vec![vec![[0, 0, 0], [0, 0, 0]], vec![[0, 0, 0], [16, 0, 0]], vec![[0, 0, 0], [16, 0, 0]]]);
+    }
+
+    #[test]
+    fn headers_with_impossible_sizes_are_rejected() {
+        let read = |header: ReplayHeader| {
+            let mut bytes = Vec::new();
+            header.write(&mut bytes).unwrap();
+            ReplayHeader::read(&mut bytes.as_slice()).map_err(|e| e.kind())
+        };
+        assert_eq!(read(header()), Ok(header()));
+
+        let mut crowded = header();
+        crowded.num_players = MAX_PLAYERS + 1;
+        crowded.ships = vec!["X-Wing".to_string(); MAX_PLAYERS + 1];
+        assert_eq!(read(crowded), Err(io::ErrorKind::InvalidData));
+
+        let mut long_name = header();
+        long_name.ships[1] = "X".repeat(MAX_SHIP_NAME_LEN + 1);
+        assert_eq!(read(long_name), Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn headers_with_a_different_spawn_layout_are_rejected() {
+        let read = |header: ReplayHeader| {
+            let mut bytes = Vec::new();
+            header.write(&mut bytes).unwrap();
+            ReplayHeader::read(&mut bytes.as_slice()).map_err(|e| e.kind())
+        };
+        assert_eq!(header().spawns.len(), 2);
+
+        let mut moved = header();
+        moved.spawns[1].translation.x += 1.0;
+        assert_eq!(read(moved), Err(io::ErrorKind::InvalidData));
+
+        let mut turned = header();
+        turned.spawns[0].rotation = Quat::IDENTITY;
+        assert_eq!(read(turned), Err(io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::gameplay::WeaponStats;
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::{BoxedFuture, FixedState},
+};
+use bevy_asset_loader::prelude::*;
+use serde::Deserialize;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::path::Path;
+
+/// Ship definitions every build ships with. Their order decides the
+/// [`ShipClass`](crate::components::ShipClass) indices, so it has to match
+/// [`ShipDefAssets`] and be the same for every peer.
+const SHIP_DEF_PATHS: [&str; 2] = ["ships/x-wing.ship.ron", "ships/interceptor.ship.ron"];
+
+/// Everything that sets one kind of ship apart, read from a `.ship.ron` file
+#[derive(Asset, TypePath, Deserialize, Debug, Clone)]
+pub struct ShipDef {
+    /// What pilots pick it by
+    pub name: String,
+    /// Scene drawn for it, as an asset path
+    pub model: String,
+    /// Cruising speed at full throttle
+    pub speed: f32,
+    /// Degrees per second at full stick
+    pub pitch_speed: f32,
+    pub roll_speed: f32,
+    /// Radius of the sphere bullets and rocks are hit tested against
+    pub radius: f32,
+    pub max_health: f32,
+    pub max_shield: f32,
+    /// Where each bullet of a cannon volley leaves from, in ship space
+    pub hardpoints: Vec<Vec3>,
+    pub cannon: WeaponStats,
+    pub missiles: u32,
+    pub flares: u32,
+}
+
+#[derive(Debug)]
+pub enum ShipDefLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl std::fmt::Display for ShipDefLoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShipDefLoaderError::Io(e) => write!(f, "failed to read ship definition: {e}"),
+            ShipDefLoaderError::Ron(e) => write!(f, "failed to parse ship definition: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ShipDefLoaderError {}
+
+impl From<std::io::Error> for ShipDefLoaderError {
+    fn from(e: std::io::Error) -> Self {
+        ShipDefLoaderError::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for ShipDefLoaderError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        ShipDefLoaderError::Ron(e)
+    }
+}
+
+#[derive(Default)]
+pub struct ShipDefLoader;
+
+impl AssetLoader for ShipDefLoader {
+    type Asset = ShipDef;
+    type Settings = ();
+    type Error = ShipDefLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<ShipDef, ShipDefLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ship.ron"]
+    }
+}
+
+/// Registers [`ShipDef`] as an asset type that loads from `.ship.ron` files
+pub struct ShipsPlugin;
+
+impl Plugin for ShipsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<ShipDef>()
+            .init_asset_loader::<ShipDefLoader>();
+    }
+}
+
+/// Kept in step with [`SHIP_DEF_PATHS`], which the attribute can't refer to
+#[derive(AssetCollection, Resource)]
+pub struct ShipDefAssets {
+    #[asset(paths("ships/x-wing.ship.ron", "ships/interceptor.ship.ron"), collection(typed))]
+    pub ships: Vec<Handle<ShipDef>>,
+}
+
+/// Every ship definition, indexed by [`ShipClass`](crate::components::ShipClass)
+#[derive(Resource, Debug, Clone)]
+pub struct ShipDefs(pub Vec<ShipDef>);
+
+impl ShipDefs {
+    pub fn from_assets(collection: &ShipDefAssets, assets: &Assets<ShipDef>) -> Self {
+        ShipDefs(
+            collection
+                .ships
+                .iter()
+                .map(|handle| assets.get(handle).expect("ship definitions are loaded").clone())
+                .collect(),
+        )
+    }
+
+    /// Reads the definitions straight from `assets_dir`, for when there is
+    /// no asset server to load them with
+    pub fn read(assets_dir: impl AsRef<Path>) -> Result<Self, String> {
+        SHIP_DEF_PATHS
+            .iter()
+            .map(|path| {
+                let path = assets_dir.as_ref().join(path);
+                let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+                ron::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+            })
+            .collect::<Result<Vec<ShipDef>, String>>()
+            .map(ShipDefs)
+    }
+
+    pub fn get(&self, class: usize) -> &ShipDef {
+        &self.0[class]
+    }
+
+    /// Name of the ship [`ShipDefs::pick`] picks for `name`
+    pub fn name_of(&self, name: Option<&str>) -> String {
+        self.get(self.pick(name)).name.clone()
+    }
+
+    /// Ship classes of the players flying the ships called `names`, by handle.
+    /// Fails on a name there's no ship for, which only a peer or a replay
+    /// with other ship definitions would send.
+    pub fn choices(&self, names: &[String]) -> Result<ShipChoices, String> {
+        names
+            .iter()
+            .map(|name| self.class_of(name).ok_or_else(|| format!("unknown ship {name}")))
+            .collect::<Result<Vec<usize>, String>>()
+            .map(ShipChoices)
+    }
+
+    /// The class of the ship called `name`, if there is one
+    pub fn class_of(&self, name: &str) -> Option<usize> {
+        self.0.iter().position(|def| def.name == name)
+    }
+
+    /// The class of the ship the local pilot asked for, or the first one if
+    /// there is no such ship or no name was given
+    pub fn pick(&self, name: Option<&str>) -> usize {
+        let Some(name) = name else {
+            return 0;
+        };
+        self.class_of(name).unwrap_or_else(|| {
+            warn!("unknown ship {name}, flying the {} instead", self.0[0].name);
+            0
+        })
+    }
+
+    /// FNV-1a over every field of every definition, in class order. Peers
+    /// whose definitions differ in any way would desync, and compare this
+    /// to find out before the match starts.
+    pub fn digest(&self) -> u64 {
+        let mut digest = Fnv1a::default();
+        digest.write_u32(self.0.len() as u32);
+        for def in &self.0 {
+            digest.write_str(&def.name);
+            digest.write_str(&def.model);
+            for value in [def.speed, def.pitch_speed, def.roll_speed, def.radius, def.max_health, def.max_shield] {
+                digest.write_f32(value);
+            }
+            digest.write_u32(def.hardpoints.len() as u32);
+            for hardpoint in &def.hardpoints {
+                for value in hardpoint.to_array() {
+                    digest.write_f32(value);
+                }
+            }
+            let cannon = &def.cannon;
+            digest.write_f32(cannon.damage);
+            digest.write_u32(cannon.shot_interval_frames);
+            for value in [cannon.heat_per_shot, cannon.cooling_rate, cannon.max_heat, cannon.recover_heat] {
+                digest.write_f32(value);
+            }
+            digest.write_u32(def.missiles);
+            digest.write_u32(def.flares);
+        }
+        digest.0
+    }
+}
+
+/// 64-bit FNV-1a, spelled out so that it's the same on every platform and
+/// with every version of every crate
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xCBF2_9CE4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+
+    /// Length first, so neighbouring strings can't run into each other
+    fn write_str(&mut self, value: &str) {
+        self.write_u32(value.len() as u32);
+        self.write(value.as_bytes());
+    }
+}
+
+/// The ship class each player handle flies, agreed on when the session starts
+#[derive(Resource, Default, Clone, Debug)]
+pub struct ShipChoices(pub Vec<usize>);
+
+impl ShipChoices {
+    pub fn get(&self, handle: usize) -> usize {
+        self.0.get(handle).copied().unwrap_or(0)
+    }
+}
+
+pub fn checksum_ship_choices(ship_choices: &ShipChoices) -> u64 {
+    let mut hasher = FixedState.build_hasher();
+    ship_choices.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_change_to_a_definition_changes_the_digest() {
+        let ship_defs = ShipDefs::read("assets").unwrap();
+        assert_eq!(ship_defs.digest(), ship_defs.clone().digest());
+        let mut tweaked = ship_defs.clone();
+        tweaked.0[1].cannon.damage += 1.0;
+        assert_ne!(tweaked.digest(), ship_defs.digest());
+        let mut reordered = ship_defs.clone();
+        reordered.0.reverse();
+        assert_ne!(reordered.digest(), ship_defs.digest());
+    }
+
+    #[test]
+    fn unknown_ships_are_only_replaced_for_local_picks() {
+        let ship_defs = ShipDefs::read("assets").unwrap();
+        let names: Vec<String> = ship_defs.0.iter().rev().map(|def| def.name.clone()).collect();
+        assert_eq!(ship_defs.choices(&names).unwrap().0, vec![1, 0]);
+        assert!(ship_defs.choices(&["Millennium Falcon".to_string()]).is_err());
+        assert_eq!(ship_defs.pick(Some("Millennium Falcon")), 0);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::math::Torus;
+use bevy::{prelude::*, utils::HashMap};
+
+/// Broad-phase grid over the wrapped cube: items are bucketed by the cell
+/// they're in, and a query only looks at the cells near it, wrapping around
+/// the faces of the cube. In arenas that don't wrap, things a whole period
+/// apart just share cells, which costs a few extra candidates.
+///
+/// Queries return items sorted, whatever order they were inserted or looked
+/// up in, so it's safe to use from the rollback simulation.
+pub struct SpatialGrid<T> {
+    torus: Torus,
+    cell_size: f32,
+    cells_per_axis: i32,
+    cells: HashMap<IVec3, Vec<T>>,
+}
+
+impl<T: Copy + Ord> SpatialGrid<T> {
+    /// Positions wrap every `period` units. Cells are at least
+    /// `min_cell_size` wide, rounded up so a whole number of them fills a period.
+    pub fn new(period: f32, min_cell_size: f32) -> Self {
+        let cells_per_axis = ((period / min_cell_size).floor() as i32).max(1);
+        SpatialGrid {
+            torus: Torus::new(period),
+            cell_size: period / cells_per_axis as f32,
+            cells_per_axis,
+            cells: HashMap::default(),
+        }
+    }
+
+    fn cell(&self, pos: Vec3) -> IVec3 {
+        let cell = (self.torus.wrap(pos) / self.cell_size).floor().as_ivec3();
+        // a point right on the far face can still round into the cell past it
+        self.wrap_cell(cell)
+    }
+
+    fn wrap_cell(&self, cell: IVec3) -> IVec3 {
+        let n = self.cells_per_axis;
+        IVec3::new(cell.x.rem_euclid(n), cell.y.rem_euclid(n), cell.z.rem_euclid(n))
+    }
+
+    pub fn insert(&mut self, pos: Vec3, item: T) {
+        let cell = self.cell(pos);
+        self.cells.entry(cell).or_default().push(item);
+    }
+
+    /// Every item within `radius` of `center`, plus some a little further
+    /// away that share a cell with them, sorted and without duplicates
+    pub fn query(&self, center: Vec3, radius: f32) -> Vec<T> {
+        let center_cell = self.cell(center);
+        // also covers a center anywhere inside its cell
+        let reach = ((radius / self.cell_size).ceil() as i32).min(self.cells_per_axis / 2 + 1);
+        let mut items = Vec::new();
+        for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    let cell = self.wrap_cell(center_cell + IVec3::new(dx, dy, dz));
+                    if let Some(cell_items) = self.cells.get(&cell) {
+                        items.extend_from_slice(cell_items);
+                    }
+                }
+            }
+        }
+        // with few cells per axis, wrapping visits some cells more than once
+        items.sort_unstable();
+        items.dedup();
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: f32 = 1000.0;
+    const CELL: f32 = 100.0;
+
+    #[test]
+    fn neighbours_are_found_across_every_seam() {
+        let edge = PERIOD / 2.0 - 0.1;
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            let mut grid = SpatialGrid::new(PERIOD, CELL);
+            grid.insert(axis * edge, 1);
+            assert_eq!(grid.query(-axis * edge, 1.0), vec![1], "across the seam along {axis}");
+
+            let mut grid = SpatialGrid::new(PERIOD, CELL);
+            grid.insert(-axis * edge, 2);
+            assert_eq!(grid.query(axis * edge, 1.0), vec![2], "back across the seam along {axis}");
+        }
+    }
+
+    #[test]
+    fn queries_reaching_around_the_whole_grid_return_each_item_once() {
+        let mut grid = SpatialGrid::new(PERIOD, CELL);
+        let mut expected = Vec::new();
+        for i in 0..50 {
+            let pos = Vec3::new(i as f32 * 37.0, i as f32 * -91.0, i as f32 * 13.0);
+            grid.insert(pos, i);
+            expected.push(i);
+        }
+        // far more than half a period, so the reach is clamped and wraps
+        // onto cells it has already visited
+        assert_eq!(grid.query(Vec3::ZERO, PERIOD * 3.0), expected);
+        assert_eq!(grid.query(Vec3::splat(PERIOD / 2.0 - 1.0), PERIOD), expected);
+    }
+
+    #[test]
+    fn results_do_not_depend_on_insertion_order() {
+        let positions: Vec<Vec3> = (0..40)
+            .map(|i| Vec3::new((i * 53 % 1000) as f32, (i * 29 % 1000) as f32, (i * 71 % 1000) as f32) - PERIOD / 2.0)
+            .collect();
+        let mut forwards = SpatialGrid::new(PERIOD, CELL);
+        for (i, pos) in positions.iter().enumerate() {
+            forwards.insert(*pos, i);
+        }
+        let mut backwards = SpatialGrid::new(PERIOD, CELL);
+        for (i, pos) in positions.iter().enumerate().rev() {
+            backwards.insert(*pos, i);
+        }
+        for center in [Vec3::ZERO, Vec3::splat(-480.0), Vec3::new(300.0, -120.0, 490.0)] {
+            let found = forwards.query(center, 250.0);
+            assert!(!found.is_empty());
+            assert!(found.windows(2).all(|w| w[0] < w[1]), "not sorted: {found:?}");
+            assert_eq!(found, backwards.query(center, 250.0));
+        }
+    }
+}
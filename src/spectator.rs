@@ -0,0 +1,146 @@
+use crate::{arena::Arena, components::*, config::GameConfig, gameplay::Scores, ships::ShipDefs};
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Align2, Color32, RichText},
+    EguiContexts,
+};
+
+const FREE_CAMERA_SPEED: f32 = 100.0;
+/// Radians per second
+const FREE_CAMERA_TURN_RATE: f32 = 1.5;
+
+/// What a spectator's camera is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpectatorView {
+    /// Chasing the ship of this player handle
+    Follow(usize),
+    /// Flown around with the pilot's keys
+    Free,
+}
+
+/// Only there while spectating
+#[derive(Resource, Debug)]
+pub struct SpectatorCamera {
+    pub view: SpectatorView,
+    /// Where the free camera is, which everything is drawn around while it's
+    /// flying
+    pub position: Vec3,
+}
+
+impl Default for SpectatorCamera {
+    fn default() -> Self {
+        SpectatorCamera {
+            view: SpectatorView::Follow(0),
+            position: Vec3::ZERO,
+        }
+    }
+}
+
+/// Camera controls and the scoreboard for spectators
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                cycle_spectator_view,
+                fly_free_camera.after(cycle_spectator_view),
+                update_spectator_hud,
+            )
+                .run_if(resource_exists::<SpectatorCamera>()),
+        );
+    }
+}
+
+/// Tab follows the next pilot, and after the last one frees the camera
+fn cycle_spectator_view(
+    keys: Res<Input<KeyCode>>,
+    mut spectator: ResMut<SpectatorCamera>,
+    players: Query<&Player>,
+) {
+    if !keys.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    let mut handles: Vec<usize> = players.iter().map(|player| player.handle).collect();
+    handles.sort();
+    spectator.view = match spectator.view {
+        SpectatorView::Free => handles.first().map_or(SpectatorView::Free, |handle| SpectatorView::Follow(*handle)),
+        SpectatorView::Follow(current) => handles
+            .into_iter()
+            .find(|handle| *handle > current)
+            .map_or(SpectatorView::Free, SpectatorView::Follow),
+    };
+}
+
+/// Pitch keys look up and down, roll keys turn, and the throttle keys move
+/// the free camera along where it looks
+fn fly_free_camera(
+    keys: Res<Input<KeyCode>>,
+    game_config: Res<GameConfig>,
+    mut spectator: ResMut<SpectatorCamera>,
+    mut cameras: Query<&mut Transform, With<PilotCamera>>,
+    arena: Res<Arena>,
+    time: Res<Time>,
+) {
+    if spectator.view != SpectatorView::Free {
+        return;
+    }
+    let bindings = &game_config.key_bindings;
+    let axis = |positive: &[KeyCode], negative: &[KeyCode]| {
+        let mut value = 0.0;
+        if keys.any_pressed(positive.iter().copied()) {
+            value += 1.0;
+        }
+        if keys.any_pressed(negative.iter().copied()) {
+            value -= 1.0;
+        }
+        value
+    };
+    let pitch = axis(&bindings.pitch_up, &bindings.pitch_down);
+    let yaw = axis(&bindings.roll_left, &bindings.roll_right);
+    let forward = axis(&bindings.throttle_up, &bindings.throttle_down);
+
+    let turn = FREE_CAMERA_TURN_RATE * time.delta_seconds();
+    for mut transform in &mut cameras {
+        transform.rotate_local_x(pitch * turn);
+        transform.rotate_local_y(yaw * turn);
+        let delta = transform.forward() * forward * FREE_CAMERA_SPEED * time.delta_seconds();
+        transform.translation = arena.wrap(transform.translation + delta);
+        spectator.position = transform.translation;
+    }
+}
+
+/// Every pilot's ship, score and hull, and what the camera is doing
+fn update_spectator_hud(
+    mut contexts: EguiContexts,
+    spectator: Res<SpectatorCamera>,
+    players: Query<(&Player, &ShipClass, &Health)>,
+    scores: Res<Scores>,
+    ship_defs: Res<ShipDefs>,
+) {
+    let mut players: Vec<_> = players.iter().collect();
+    players.sort_by_key(|(player, ..)| player.handle);
+    let view = match spectator.view {
+        SpectatorView::Follow(handle) => format!("Following pilot {}", handle + 1),
+        SpectatorView::Free => "Free camera".to_string(),
+    };
+
+    egui::Area::new("spectator")
+        .anchor(Align2::RIGHT_TOP, (-10., 40.))
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(RichText::new(format!("{view} (Tab to switch)")).color(Color32::WHITE));
+            for (player, class, health) in players {
+                let score = scores.0.get(player.handle).copied().unwrap_or(0);
+                ui.label(
+                    RichText::new(format!(
+                        "Pilot {}  {}  {score} kills  hull {:.0}",
+                        player.handle + 1,
+                        ship_defs.get(class.0).name,
+                        health.0.max(0.0),
+                    ))
+                    .color(Color32::WHITE),
+                );
+            }
+        });
+}